@@ -0,0 +1,372 @@
+use codesnip_core::{LinkedSnippet, SnippetMap};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Leading bytes of a cache file.
+const MAGIC: &[u8; 8] = b"CODESNIP";
+/// Version of the cache layout after the header, bumped on incompatible changes.
+pub const FORMAT_VERSION: u32 = 7;
+
+/// Data saved by `cache`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cache {
+    pub header: Header,
+    /// Formatted snippets.
+    pub map: SnippetMap,
+    /// Intermediate results to rebuild only what changed, empty unless built from a source config.
+    ///
+    /// Only kept in the binary format.
+    #[serde(skip)]
+    pub incremental: Incremental,
+}
+
+/// Serialization of a cache file chosen by extension, binary unless `.json` or `.toml`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheFormat {
+    Bincode,
+    Json,
+    Toml,
+}
+
+/// Metadata written before the contents of a cache file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    /// `0` for a file written before the header existed.
+    pub format_version: u32,
+    pub codesnip_version: String,
+    /// Format option of the source config.
+    pub format: Option<String>,
+    /// Fingerprint of the source config.
+    pub source_config: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Incremental {
+    /// Parsed sources keyed by fingerprint of their settings.
+    pub sources: BTreeMap<String, SourceCache>,
+    /// Formatted contents keyed by hash of format option and unformatted contents.
+    pub formatted: BTreeMap<String, String>,
+    /// Snippets of plain caches merged with the sources and their origins, merged again when
+    /// rebuilt.
+    pub caches: Vec<(SnippetMap, String)>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SourceCache {
+    /// Hashes of all files of the source, relative to the checkout for git sources.
+    pub files: BTreeMap<PathBuf, String>,
+    /// Unformatted snippets of the source.
+    pub map: SnippetMap,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            codesnip_version: env!("CARGO_PKG_VERSION").to_string(),
+            format: None,
+            source_config: None,
+        }
+    }
+}
+
+impl Header {
+    fn check(&self) -> anyhow::Result<()> {
+        if self.format_version != FORMAT_VERSION {
+            anyhow::bail!(
+                "cache format version {} (codesnip {}) is not supported by codesnip {} (format version {}), rebuild the cache",
+                self.format_version,
+                self.codesnip_version,
+                env!("CARGO_PKG_VERSION"),
+                FORMAT_VERSION
+            );
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format_version {
+            0 => writeln!(f, "format version: 0 (without header)")?,
+            version => writeln!(f, "format version: {}", version)?,
+        }
+        writeln!(f, "codesnip version: {}", self.codesnip_version)?;
+        writeln!(f, "format: {}", self.format.as_deref().unwrap_or("-"))?;
+        writeln!(
+            f,
+            "source config: {}",
+            self.source_config.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+impl CacheFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext == "json" => Self::Json,
+            Some(ext) if ext == "toml" => Self::Toml,
+            _ => Self::Bincode,
+        }
+    }
+}
+
+impl Cache {
+    pub fn encode_as(&self, format: CacheFormat) -> anyhow::Result<Vec<u8>> {
+        Ok(match format {
+            CacheFormat::Bincode => self.encode()?,
+            CacheFormat::Json => serde_json::to_vec_pretty(self)?,
+            CacheFormat::Toml => toml::to_string(self)?.into_bytes(),
+        })
+    }
+
+    pub fn decode_as(bytes: &[u8], format: CacheFormat) -> anyhow::Result<Self> {
+        let cache: Self = match format {
+            CacheFormat::Bincode => return Self::decode(bytes),
+            CacheFormat::Json => serde_json::from_slice(bytes)?,
+            CacheFormat::Toml => toml::from_slice(bytes)?,
+        };
+        cache.header.check()?;
+        Ok(cache)
+    }
+
+    pub fn decode_header_as(bytes: &[u8], format: CacheFormat) -> anyhow::Result<Header> {
+        #[derive(Deserialize)]
+        struct Partial {
+            header: Header,
+        }
+        Ok(match format {
+            CacheFormat::Bincode => {
+                let (header, _) = Self::decode_header(bytes)?;
+                // Without the header, only a file decoded in the legacy layout is a cache.
+                if header.format_version == 0 {
+                    Self::decode(bytes)?;
+                }
+                header
+            }
+            CacheFormat::Json => serde_json::from_slice::<Partial>(bytes)?.header,
+            CacheFormat::Toml => toml::from_slice::<Partial>(bytes)?.header,
+        })
+    }
+
+    fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        let config = bincode::config::standard();
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::serde::encode_to_vec(&self.header, config)?);
+        bytes.extend(bincode::serde::encode_to_vec(
+            (&self.map, &self.incremental),
+            config,
+        )?);
+        Ok(bytes)
+    }
+
+    /// Decode a cache file, one written before the header existed is migrated.
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let config = bincode::config::standard();
+        let (header, len) = Self::decode_header(bytes)?;
+        match header.format_version {
+            0 => {
+                #[derive(Deserialize)]
+                struct LegacySnippet {
+                    contents: String,
+                    includes: BTreeSet<String>,
+                }
+                let legacy: BTreeMap<String, LegacySnippet> =
+                    match bincode::serde::decode_from_slice(bytes, config) {
+                        Ok((legacy, len)) if len == bytes.len() => legacy,
+                        _ => anyhow::bail!("not a codesnip cache file"),
+                    };
+                let map = legacy
+                    .into_iter()
+                    .enumerate()
+                    .map(|(order, (name, legacy))| {
+                        let link = LinkedSnippet {
+                            contents: legacy.contents,
+                            includes: legacy.includes,
+                            order,
+                            ..Default::default()
+                        };
+                        (name, link)
+                    })
+                    .collect();
+                Ok(Self {
+                    header,
+                    map,
+                    incremental: Default::default(),
+                })
+            }
+            _ => {
+                header.check()?;
+                let ((map, incremental), _) =
+                    bincode::serde::decode_from_slice(&bytes[len..], config)?;
+                Ok(Self {
+                    header,
+                    map,
+                    incremental,
+                })
+            }
+        }
+    }
+
+    /// Decode the header and return it with its length in bytes.
+    fn decode_header(bytes: &[u8]) -> anyhow::Result<(Header, usize)> {
+        match bytes.strip_prefix(MAGIC) {
+            Some(rest) => {
+                let (header, len): (Header, _) =
+                    bincode::serde::decode_from_slice(rest, bincode::config::standard())
+                        .map_err(|err| anyhow::anyhow!("invalid cache header: {}", err))?;
+                Ok((header, MAGIC.len() + len))
+            }
+            None => Ok((
+                Header {
+                    format_version: 0,
+                    codesnip_version: "unknown".to_string(),
+                    ..Default::default()
+                },
+                0,
+            )),
+        }
+    }
+}
+
+impl Incremental {
+    pub fn append(&mut self, other: Self) {
+        self.sources.extend(other.sources);
+        self.formatted.extend(other.formatted);
+        self.caches.extend(other.caches);
+    }
+}
+
+impl SourceCache {
+    /// Whether no file has changed since cached.
+    pub fn is_fresh(&self, base: &Path) -> bool {
+        !self.files.is_empty()
+            && self.files.iter().all(|(path, hash)| {
+                std::fs::read(base.join(path)).is_ok_and(|contents| hash_bytes(&contents) == *hash)
+            })
+    }
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+fn test_cache() -> Cache {
+    let mut cache = Cache::default();
+    cache.header.format = Some("rustfmt".to_string());
+    cache.map = crate::test_map(&[("a", "fn a() {}\n"), ("b", "fn b() {}\n")]);
+    if let Some(link) = cache.map.map.get_mut("b") {
+        link.push_includes(["a".to_string()]);
+    }
+    cache
+        .incremental
+        .formatted
+        .insert("hash".to_string(), "fn a() {}\n".to_string());
+    cache
+}
+
+#[test]
+fn test_cache_formats() {
+    let cache = test_cache();
+    for format in [CacheFormat::Bincode, CacheFormat::Json, CacheFormat::Toml] {
+        let bytes = cache.encode_as(format).unwrap();
+        let decoded = Cache::decode_as(&bytes, format).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&cache).unwrap(),
+            "{:?}",
+            format
+        );
+        // The incremental data is only kept in the binary format.
+        assert_eq!(
+            decoded.incremental.formatted.len(),
+            (format == CacheFormat::Bincode) as usize
+        );
+    }
+
+    // Caches written by hand may omit fields added later.
+    let json = format!(
+        r#"{{
+            "header": {{ "format_version": {}, "codesnip_version": "0.1.0" }},
+            "map": {{ "a": {{ "contents": "fn a() {{}}", "includes": [] }} }}
+        }}"#,
+        FORMAT_VERSION
+    );
+    let decoded = Cache::decode_as(json.as_bytes(), CacheFormat::Json).unwrap();
+    assert_eq!(decoded.map.map["a"].order, 0);
+}
+
+#[test]
+fn test_cache_header() {
+    let cache = test_cache();
+    let bytes = cache.encode_as(CacheFormat::Bincode).unwrap();
+    assert!(bytes.starts_with(MAGIC));
+    for format in [CacheFormat::Bincode, CacheFormat::Json, CacheFormat::Toml] {
+        let header = Cache::decode_header_as(&cache.encode_as(format).unwrap(), format).unwrap();
+        assert_eq!(header.format_version, FORMAT_VERSION);
+        assert_eq!(header.codesnip_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(header.format.as_deref(), Some("rustfmt"));
+    }
+
+    let mut future = test_cache();
+    future.header.format_version = FORMAT_VERSION + 1;
+    for format in [CacheFormat::Bincode, CacheFormat::Json, CacheFormat::Toml] {
+        let bytes = future.encode_as(format).unwrap();
+        let err = Cache::decode_as(&bytes, format).unwrap_err();
+        assert!(err.to_string().ends_with("rebuild the cache"), "{}", err);
+        // The header of an unsupported version can still be inspected.
+        let header = Cache::decode_header_as(&bytes, format).unwrap();
+        assert_eq!(header.format_version, FORMAT_VERSION + 1);
+    }
+}
+
+#[test]
+fn test_cache_legacy() {
+    #[derive(Serialize)]
+    struct LegacySnippet {
+        contents: String,
+        includes: BTreeSet<String>,
+    }
+    let legacy = BTreeMap::from([
+        (
+            "a".to_string(),
+            LegacySnippet {
+                contents: "fn a() {}\n".to_string(),
+                includes: BTreeSet::new(),
+            },
+        ),
+        (
+            "b".to_string(),
+            LegacySnippet {
+                contents: "fn b() {}\n".to_string(),
+                includes: BTreeSet::from(["a".to_string()]),
+            },
+        ),
+    ]);
+    let bytes = bincode::serde::encode_to_vec(&legacy, bincode::config::standard()).unwrap();
+    let cache = Cache::decode_as(&bytes, CacheFormat::Bincode).unwrap();
+    assert_eq!(cache.header.format_version, 0);
+    assert_eq!(cache.map.map["b"].contents, "fn b() {}\n");
+    assert_eq!(cache.map.includes_of("b"), BTreeSet::from(["a"]));
+    assert_eq!(cache.map.map["a"].order, 0);
+    assert_eq!(cache.map.map["b"].order, 1);
+    let header = Cache::decode_header_as(&bytes, CacheFormat::Bincode).unwrap();
+    assert_eq!(header.format_version, 0);
+    for garbage in [&b"not a cache"[..], &[0, 1, 2]] {
+        for result in [
+            Cache::decode_as(garbage, CacheFormat::Bincode).map(|_| ()),
+            Cache::decode_header_as(garbage, CacheFormat::Bincode).map(|_| ()),
+        ] {
+            assert_eq!(result.unwrap_err().to_string(), "not a codesnip cache file");
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::Context as _;
+use codesnip_core::SnippetMap;
+use serde::Serialize;
+use std::{collections::BTreeSet, fmt::Write as _, str::FromStr};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub enum DepsFormat {
+    #[default]
+    Text,
+    Json,
+    Dot,
+}
+
+impl FromStr for DepsFormat {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "dot" => Ok(Self::Dot),
+            _ => Err("expected one of [text|json|dot]"),
+        }
+    }
+}
+
+impl DepsFormat {
+    pub const POSSIBLE_VALUES: [&'static str; 3] = ["text", "json", "dot"];
+}
+
+#[derive(Serialize)]
+struct Deps<'s> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tree: Option<Tree<'s>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    includes: Option<BTreeSet<&'s str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    included_by: Option<BTreeSet<&'s str>>,
+    cycles: Vec<Vec<&'s str>>,
+}
+
+#[derive(Serialize)]
+struct Tree<'s> {
+    name: &'s str,
+    /// Already shown above, or missing from the map.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<&'static str>,
+    includes: Vec<Tree<'s>>,
+}
+
+/// Show the include graph around `name`, or of the whole map.
+pub fn execute(map: &SnippetMap, name: Option<&str>, format: DepsFormat) -> anyhow::Result<String> {
+    let name = match name {
+        Some(name) => Some(
+            map.resolve(name)
+                .with_context(|| format!("snippet `{}` not found", name))?
+                .0,
+        ),
+        None => None,
+    };
+    let cycles: Vec<Vec<&str>> = map
+        .cycles()
+        .into_iter()
+        .filter(|cycle| name.is_none_or(|name| cycle.contains(&name)))
+        .collect();
+    let deps = Deps {
+        tree: name.map(|name| tree(map, name, &mut BTreeSet::new())),
+        includes: name.map(|name| map.includes_of(name)),
+        included_by: name.map(|name| map.included_by(name)),
+        cycles,
+    };
+    Ok(match format {
+        DepsFormat::Text => to_text(&deps),
+        DepsFormat::Json => serde_json::to_string(&deps)?,
+        DepsFormat::Dot => to_dot(map, &deps),
+    })
+}
+
+fn tree<'s>(map: &'s SnippetMap, name: &'s str, shown: &mut BTreeSet<&'s str>) -> Tree<'s> {
+    let Some(link) = map.map.get(name) else {
+        return Tree {
+            name,
+            note: Some("missing"),
+            includes: Vec::new(),
+        };
+    };
+    if !shown.insert(name) {
+        return Tree {
+            name,
+            note: Some("*"),
+            includes: Vec::new(),
+        };
+    }
+    Tree {
+        name,
+        note: None,
+        includes: link
+            .includes
+            .iter()
+            .map(|include| tree(map, include, shown))
+            .collect(),
+    }
+}
+
+fn to_text(deps: &Deps) -> String {
+    fn push_tree(s: &mut String, tree: &Tree, indent: &str, last: bool, root: bool) {
+        if !root {
+            s.push_str(indent);
+            s.push_str(if last { "└── " } else { "├── " });
+        }
+        s.push_str(tree.name);
+        if let Some(note) = tree.note {
+            let _ = write!(s, " ({})", note);
+        }
+        s.push('\n');
+        let indent = if root {
+            String::new()
+        } else {
+            format!("{}{}", indent, if last { "    " } else { "│   " })
+        };
+        for (i, child) in tree.includes.iter().enumerate() {
+            push_tree(s, child, &indent, i + 1 == tree.includes.len(), false);
+        }
+    }
+
+    let mut s = String::new();
+    if let Some(tree) = &deps.tree {
+        push_tree(&mut s, tree, "", true, true);
+    }
+    if let Some(included_by) = &deps.included_by {
+        let included_by: Vec<_> = included_by.iter().cloned().collect();
+        let included_by = if included_by.is_empty() {
+            "-".to_string()
+        } else {
+            included_by.join(" ")
+        };
+        let _ = writeln!(s, "included by: {}", included_by);
+    }
+    if !deps.cycles.is_empty() {
+        s.push_str("cycles:\n");
+        for cycle in deps.cycles.iter() {
+            let _ = writeln!(s, "  {}", cycle.join(" "));
+        }
+    }
+    s
+}
+
+fn to_dot(map: &SnippetMap, deps: &Deps) -> String {
+    let nodes: BTreeSet<&str> = match (&deps.tree, &deps.includes, &deps.included_by) {
+        (Some(tree), Some(includes), Some(included_by)) => includes
+            .iter()
+            .chain(included_by)
+            .cloned()
+            .chain([tree.name])
+            .collect(),
+        _ => map.map.keys().map(|name| name.as_str()).collect(),
+    };
+    let in_cycle: BTreeSet<&str> = deps.cycles.iter().flatten().cloned().collect();
+    let mut s = String::from("digraph codesnip {\n");
+    for node in nodes.iter() {
+        let mut attrs = Vec::new();
+        if deps.tree.as_ref().is_some_and(|tree| tree.name == *node) {
+            attrs.push("style=bold");
+        }
+        if in_cycle.contains(node) {
+            attrs.push("color=red");
+        }
+        if !map.map.contains_key(*node) {
+            attrs.push("style=dashed");
+        }
+        let _ = writeln!(s, "    {:?} [{}];", node, attrs.join(", "));
+    }
+    for node in nodes.iter() {
+        for include in map
+            .map
+            .get(*node)
+            .into_iter()
+            .flat_map(|link| &link.includes)
+        {
+            if nodes.contains(include.as_str()) {
+                let _ = writeln!(s, "    {:?} -> {:?};", node, include);
+            }
+        }
+    }
+    s.push_str("}\n");
+    s
+}
+
+#[test]
+fn test_deps_text() {
+    let mut map = crate::test_map(&[("a", "fn a() {}\n"), ("b", "fn b() {}\n")]);
+    if let Some(link) = map.map.get_mut("b") {
+        link.push_includes(["a".to_string()]);
+    }
+    assert_eq!(
+        execute(&map, Some("b"), DepsFormat::Text).unwrap(),
+        "b\n└── a\nincluded by: -\n"
+    );
+    assert_eq!(
+        execute(&map, Some("a"), DepsFormat::Text).unwrap(),
+        "a\nincluded by: b\n"
+    );
+}
@@ -0,0 +1,306 @@
+use codesnip_core::{BundleOrder, Error::FileNotFound, ItemExt as _, SnippetMap};
+use quote::ToTokens as _;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+use syn::{Item, ItemUse, UseTree, spanned::Spanned as _, visit::Visit};
+
+/// Bundle snippets used by `use` paths in `target` into it.
+pub fn execute(
+    map: &SnippetMap,
+    target: &Path,
+    crate_names: &[String],
+    lenient: bool,
+    order: BundleOrder,
+) -> anyhow::Result<String> {
+    let source =
+        std::fs::read_to_string(target).map_err(|err| FileNotFound(target.to_path_buf(), err))?;
+    expand(map, &source, crate_names, lenient, order)
+}
+
+pub fn expand(
+    map: &SnippetMap,
+    source: &str,
+    crate_names: &[String],
+    lenient: bool,
+    order: BundleOrder,
+) -> anyhow::Result<String> {
+    let file = syn::parse_file(source)?;
+    let definers = top_level_definers(map);
+
+    let mut uses = CollectUses::default();
+    let mut local_mods = Vec::new();
+    for item in file.items.iter() {
+        match item {
+            Item::Use(item) => uses.items.push((item, true)),
+            Item::Mod(item) => {
+                local_mods.push(item.ident.to_string());
+                uses.visit_item_mod(item);
+            }
+            item => uses.visit_item(item),
+        }
+    }
+
+    let mut names = Vec::new();
+    let mut replaces = Vec::new();
+    for (item, root) in uses.items {
+        let mut leaves = Vec::new();
+        flatten_use_tree(&item.tree, &mut Vec::new(), &mut leaves);
+        leaves.retain(|leaf| !local_mods.contains(&leaf.root));
+        if !leaves.iter().any(|leaf| is_library(leaf, crate_names)) {
+            continue;
+        }
+        let attrs = item
+            .attrs
+            .iter()
+            .map(|attr| attr.to_token_stream().to_string() + " ")
+            .collect::<String>();
+        let vis = item.vis.to_token_stream().to_string();
+        let vis = if vis.is_empty() { vis } else { vis + " " };
+        let mut resolved_any = false;
+        let mut lines = Vec::new();
+        for leaf in leaves {
+            let resolved = if is_library(&leaf, crate_names) {
+                resolve(&leaf, &definers)?
+            } else {
+                None
+            };
+            match resolved {
+                Some((name, path)) => {
+                    resolved_any = true;
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                    if !(root && path.len() == 1 && leaf.rename.is_none() && !leaf.glob) {
+                        lines.push(format!(
+                            "{}{}use {};",
+                            attrs,
+                            vis,
+                            leaf.to_path_string(
+                                std::iter::once("crate").chain(path.iter().map(|s| s.as_str()))
+                            )
+                        ));
+                    }
+                }
+                None => {
+                    if is_library(&leaf, crate_names) {
+                        eprintln!("warning: `{}` is not found in snippets.", leaf.original());
+                    }
+                    lines.push(format!("{}{}use {};", attrs, vis, leaf.original()));
+                }
+            }
+        }
+        if resolved_any {
+            replaces.push((item.span().byte_range(), lines.join("\n")));
+        }
+    }
+
+    let mut contents = source.to_string();
+    for (mut range, text) in replaces.into_iter().rev() {
+        if text.is_empty() && contents[range.end..].starts_with('\n') {
+            range.end += 1;
+        }
+        contents.replace_range(range, &text);
+    }
+
+    map.bundle_into(
+        &mut contents,
+        names.iter().map(|name| name.as_str()),
+        Default::default(),
+        lenient,
+        order,
+    )?;
+    Ok(contents)
+}
+
+#[derive(Default)]
+struct CollectUses<'a> {
+    items: Vec<(&'a ItemUse, bool)>,
+}
+
+impl<'a> Visit<'a> for CollectUses<'a> {
+    fn visit_item_use(&mut self, item: &'a ItemUse) {
+        self.items.push((item, false));
+    }
+}
+
+/// A single path of a `use` tree.
+struct UseLeaf {
+    root: String,
+    path: Vec<String>,
+    rename: Option<String>,
+    glob: bool,
+}
+
+impl UseLeaf {
+    fn new(prefix: &[String], last: Option<String>, rename: Option<String>, glob: bool) -> Self {
+        let mut path: Vec<String> = prefix.iter().chain(&last).cloned().collect();
+        let root = path.remove(0);
+        Self {
+            root,
+            path,
+            rename,
+            glob,
+        }
+    }
+
+    fn to_path_string<'s>(&self, path: impl IntoIterator<Item = &'s str>) -> String {
+        let mut s = path.into_iter().collect::<Vec<_>>().join("::");
+        if self.glob {
+            s.push_str("::*");
+        }
+        if let Some(rename) = &self.rename {
+            s.push_str(" as ");
+            s.push_str(rename);
+        }
+        s
+    }
+
+    fn original(&self) -> String {
+        self.to_path_string(
+            std::iter::once(self.root.as_str()).chain(self.path.iter().map(|s| s.as_str())),
+        )
+    }
+}
+
+fn flatten_use_tree(tree: &UseTree, prefix: &mut Vec<String>, leaves: &mut Vec<UseLeaf>) {
+    match tree {
+        UseTree::Path(path) => {
+            prefix.push(path.ident.to_string());
+            flatten_use_tree(&path.tree, prefix, leaves);
+            prefix.pop();
+        }
+        UseTree::Name(name) => {
+            leaves.push(UseLeaf::new(
+                prefix,
+                Some(name.ident.to_string()),
+                None,
+                false,
+            ));
+        }
+        UseTree::Rename(rename) => leaves.push(UseLeaf::new(
+            prefix,
+            Some(rename.ident.to_string()),
+            Some(rename.rename.to_string()),
+            false,
+        )),
+        UseTree::Glob(_) => {
+            if !prefix.is_empty() {
+                leaves.push(UseLeaf::new(prefix, None, None, true));
+            }
+        }
+        UseTree::Group(group) => {
+            for tree in group.items.iter() {
+                flatten_use_tree(tree, prefix, leaves);
+            }
+        }
+    }
+}
+
+fn is_library(leaf: &UseLeaf, crate_names: &[String]) -> bool {
+    crate_names.contains(&leaf.root)
+}
+
+/// Snippets defining each top-level name.
+fn top_level_definers(map: &SnippetMap) -> BTreeMap<String, BTreeSet<&str>> {
+    let mut definers: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    for (name, link) in map.map.iter() {
+        if let Ok(file) = syn::parse_file(&link.contents) {
+            for item in file.items.iter() {
+                if let Some(ident) = item.get_default_name() {
+                    definers.entry(ident).or_default().insert(name);
+                }
+            }
+        }
+    }
+    definers
+}
+
+/// Find the snippet defining the leaf and the path to it from the crate root.
+///
+/// A name defined by several snippets resolves to the snippet of the same name, or is an error.
+fn resolve(
+    leaf: &UseLeaf,
+    definers: &BTreeMap<String, BTreeSet<&str>>,
+) -> anyhow::Result<Option<(String, Vec<String>)>> {
+    for (i, ident) in leaf.path.iter().enumerate() {
+        let Some(names) = definers.get(ident) else {
+            continue;
+        };
+        let name = match names.get(ident.as_str()) {
+            Some(name) => name,
+            None if names.len() == 1 => names.first().unwrap(),
+            None => anyhow::bail!(
+                "`{}` in `use {}` is defined by several snippets: {}",
+                ident,
+                leaf.original(),
+                names
+                    .iter()
+                    .map(|name| format!("`{}`", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        return Ok(Some((name.to_string(), leaf.path[i..].to_vec())));
+    }
+    Ok(None)
+}
+
+#[test]
+fn test_expand_use_tree() {
+    let map = crate::test_map(&[
+        ("gcd", "pub fn gcd() {}\n"),
+        ("lcm", "pub fn lcm() {}\n"),
+        (
+            "math",
+            "pub mod math { pub fn pow() {} pub fn sqrt() {} }\n",
+        ),
+    ]);
+    let expand = |source: &str| {
+        expand(
+            &map,
+            source,
+            &["lib".to_string()],
+            false,
+            BundleOrder::Alphabetical,
+        )
+        .unwrap()
+    };
+
+    // A top-level name needs no `use`, other paths are rewritten from the crate root.
+    assert_eq!(
+        expand("use lib::{gcd, math::{pow, sqrt as s}};\nuse std::io;\nfn main() {}\n"),
+        "use crate::math::pow;\nuse crate::math::sqrt as s;\nuse std::io;\nfn main() {}\n\
+         // codesnip-guard: gcd\npub fn gcd() {}\n\
+         // codesnip-guard: math\npub mod math { pub fn pow() {} pub fn sqrt() {} }\n"
+    );
+    assert_eq!(
+        expand("use lib::math::*;\nuse lib::lcm as l;\n"),
+        "use crate::math::*;\nuse crate::lcm as l;\n\
+         // codesnip-guard: math\npub mod math { pub fn pow() {} pub fn sqrt() {} }\n\
+         // codesnip-guard: lcm\npub fn lcm() {}\n"
+    );
+    // Paths of other crates and unknown names are kept.
+    assert_eq!(
+        expand("use serde::Serialize;\nuse lib::{gcd, unknown};\n"),
+        "use serde::Serialize;\nuse lib::unknown;\n// codesnip-guard: gcd\npub fn gcd() {}\n"
+    );
+}
+
+#[test]
+fn test_expand_ambiguous() {
+    let map = crate::test_map(&[("a", "pub fn f() {}\n"), ("b", "pub fn f() {}\n")]);
+    let err = expand(
+        &map,
+        "use lib::f;\n",
+        &["lib".to_string()],
+        false,
+        Default::default(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "`f` in `use lib::f` is defined by several snippets: `a`, `b`"
+    );
+}
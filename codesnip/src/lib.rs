@@ -1,16 +1,29 @@
+pub mod cache;
+pub mod deps;
+pub mod expand;
+pub mod lsp;
 pub mod mapping;
+pub mod search;
+pub mod show;
 pub mod source;
 pub mod verify;
+pub mod watch;
 
-use crate::mapping::SnippetMapExt as _;
+use crate::{
+    cache::{Cache, CacheFormat, Header, Incremental},
+    mapping::{Merger, SnippetMapExt as _, deprecation_warnings, describe},
+};
 use anyhow::Context as _;
 pub use codesnip_attr::{entry, skip};
-use codesnip_core::{Error::FileNotFound, SnippetMap};
+use codesnip_core::{BundleOrder, CollisionPolicy, Error::FileNotFound, SnippetMap, find_guards};
+use deps::DepsFormat;
+use search::OutputFormat;
 use serde_json::to_string;
 use source::Sources;
 use std::{
+    collections::BTreeSet,
     fs::File,
-    io::{Read as _, Write as _, stdout},
+    io::{Read as _, Write as _, stdin, stdout},
     path::{Path, PathBuf},
 };
 use structopt::{
@@ -31,14 +44,30 @@ pub enum Opt {
 #[derive(Debug, StructOpt)]
 #[structopt(rename_all = "kebab-case")]
 pub struct Config {
-    /// Use cached data.
+    /// Use cached data. With `--source-config`, a cache built from it is used to rebuild only
+    /// what changed.
     #[structopt(long, value_name = "FILE", parse(from_os_str))]
     pub use_cache: Vec<PathBuf>,
 
-    /// Source config file path. see https://github.com/to-omer/codesnip#source-config
+    /// Source config file path, looked up from the current directory if neither this nor
+    /// `--use-cache` is given. see https://github.com/to-omer/codesnip#source-config
     #[structopt(long, value_name = "FILE", parse(from_os_str))]
     pub source_config: Option<PathBuf>,
 
+    /// Whether `source_config` was looked up, then its relative paths are resolved from its
+    /// directory.
+    #[structopt(skip)]
+    pub discovered: bool,
+
+    /// How to merge snippets of the same name, overrides the source config.
+    #[structopt(long, value_name = "POLICY", possible_values = &CollisionPolicy::POSSIBLE_VALUES)]
+    pub collision: Option<CollisionPolicy>,
+
+    /// Run the subcommand again whenever the source config or a file of its local sources
+    /// changes.
+    #[structopt(long)]
+    pub watch: bool,
+
     #[structopt(subcommand)]
     pub cmd: Command,
 }
@@ -47,15 +76,48 @@ pub struct Config {
 pub enum Command {
     /// Save analyzed data into file.
     Cache {
-        /// Output file.
+        /// Output file, JSON or TOML if the extension is `.json` or `.toml`.
         #[structopt(value_name = "FILE", parse(from_os_str))]
         output: PathBuf,
     },
+    /// Print metadata of a cache file.
+    Inspect {
+        /// Cache file.
+        #[structopt(value_name = "FILE", parse(from_os_str))]
+        file: PathBuf,
+    },
     /// List names.
     List {
         /// Not hide `entry(name = "_...")`.
         #[structopt(long)]
         not_hide: bool,
+        /// Also list aliases.
+        #[structopt(long)]
+        aliases: bool,
+        /// One snippet per line with its description, category and tags.
+        #[structopt(long)]
+        long: bool,
+        /// Only snippets with all of the tags.
+        #[structopt(long, value_name = "TAG")]
+        tag: Vec<String>,
+        /// Only snippets in the category.
+        #[structopt(long, value_name = "CATEGORY")]
+        category: Option<String>,
+    },
+    /// Search snippets by fuzzy match on names, aliases, defined identifiers and doc comments.
+    Search {
+        /// Search query.
+        #[structopt(value_name = "QUERY")]
+        query: String,
+        /// Maximum number of results.
+        #[structopt(long, value_name = "N")]
+        limit: Option<usize>,
+        /// Not hide `entry(name = "_...")`.
+        #[structopt(long)]
+        not_hide: bool,
+        /// Output format.
+        #[structopt(long, value_name = "FORMAT", default_value = "text", possible_values = &OutputFormat::POSSIBLE_VALUES)]
+        format: OutputFormat,
     },
     /// Output snippet for VSCode.
     Snippet {
@@ -65,6 +127,9 @@ pub enum Command {
         /// ignore includes
         #[structopt(long)]
         ignore_include: bool,
+        /// Also output snippets for aliases.
+        #[structopt(long)]
+        aliases: bool,
     },
     /// Bundle
     Bundle {
@@ -74,7 +139,55 @@ pub enum Command {
         /// excludes.
         #[structopt(short, long, value_name = "NAME")]
         excludes: Vec<String>,
+        /// Append to an existing file (`-` for stdin), except snippets already guarded in it.
+        #[structopt(long, value_name = "FILE", parse(from_os_str))]
+        into: Option<PathBuf>,
+        /// Write the result back to the file given by `--into`.
+        #[structopt(long, requires = "into")]
+        in_place: bool,
+        /// Skip unresolved includes instead of failing.
+        #[structopt(long)]
+        lenient: bool,
+        /// Order of bundled snippets.
+        #[structopt(long, value_name = "ORDER", default_value = "alphabetical", possible_values = &BundleOrder::POSSIBLE_VALUES)]
+        order: BundleOrder,
+    },
+    /// Bundle snippets used by a file through `use` paths into it.
+    Expand {
+        /// Target file.
+        #[structopt(value_name = "FILE", parse(from_os_str))]
+        file: PathBuf,
+        /// Crate name of the library in `use` paths.
+        #[structopt(long, value_name = "NAME", required = true)]
+        crate_name: Vec<String>,
+        /// Skip unresolved includes instead of failing.
+        #[structopt(long)]
+        lenient: bool,
+        /// Order of bundled snippets.
+        #[structopt(long, value_name = "ORDER", default_value = "alphabetical", possible_values = &BundleOrder::POSSIBLE_VALUES)]
+        order: BundleOrder,
     },
+    /// Show contents, includes, source location and size of a snippet.
+    Show {
+        /// snippet name or alias.
+        #[structopt(value_name = "NAME")]
+        name: String,
+        /// Output format.
+        #[structopt(long, value_name = "FORMAT", default_value = "text", possible_values = &OutputFormat::POSSIBLE_VALUES)]
+        format: OutputFormat,
+    },
+    /// Show the include graph: include tree, reverse dependencies and cycles.
+    Deps {
+        /// snippet name, default whole graph.
+        #[structopt(value_name = "NAME")]
+        name: Option<String>,
+        /// Output format.
+        #[structopt(long, value_name = "FORMAT", default_value = "text", possible_values = &DepsFormat::POSSIBLE_VALUES)]
+        format: DepsFormat,
+    },
+    /// Serve completion, hover and code actions of snippets over the language server protocol
+    /// on stdio.
+    Lsp,
     /// Verify
     Verify {
         #[structopt(long, value_name = "TOOLCHAIN", default_value = "stable")]
@@ -97,65 +210,303 @@ impl Opt {
         StructOpt::from_args()
     }
 
-    pub fn execute(&self) -> anyhow::Result<()> {
+    pub fn execute(self) -> anyhow::Result<()> {
         let Opt::Codesnip(opt) = self;
         opt.execute()
     }
 }
 
 impl Config {
-    pub fn execute(&self) -> anyhow::Result<()> {
-        let mut map = if let Some(source_config) = &self.source_config {
-            let target_config = Sources::load(source_config)?;
-            target_config.snippet_map()?
-        } else {
-            SnippetMap::new()
+    pub fn execute(mut self) -> anyhow::Result<()> {
+        let needs_snippets = !matches!(self.cmd, Command::Inspect { .. });
+        if self.source_config.is_none() && self.use_cache.is_empty() && needs_snippets {
+            let source_config = Sources::discover(&std::env::current_dir()?)?;
+            eprintln!("using source config `{}`", source_config.display());
+            self.source_config = Some(source_config);
+            self.discovered = true;
+        }
+        if self.watch {
+            return watch::execute(&self);
+        }
+        let (previous, caches) = self.load_caches()?;
+        self.cmd.execute(self.build(&previous, &caches)?)
+    }
+
+    /// Load the source config if given.
+    fn load_sources(&self) -> anyhow::Result<Option<Sources>> {
+        let Some(source_config) = &self.source_config else {
+            return Ok(None);
         };
+        if self.discovered {
+            Sources::load_discovered(source_config).map(Some)
+        } else {
+            Sources::load(source_config).map(Some)
+        }
+    }
 
+    /// Load `--use-cache` files, returning data to rebuild the source config incrementally and
+    /// snippet maps with their origins.
+    ///
+    /// Only caches built from the source config are rebuilt, others are merged as they are. Plain
+    /// caches merged into a rebuilt cache are merged again.
+    fn load_caches(&self) -> anyhow::Result<(Incremental, Vec<(SnippetMap, String)>)> {
+        let sources = self.load_sources()?;
+        let mut previous = Incremental::default();
+        let mut caches = Vec::new();
         let mut buf = Vec::new();
         for cache in self.use_cache.iter() {
             buf.clear();
             let mut file = File::open(cache).map_err(|err| FileNotFound(cache.clone(), err))?;
             file.read_to_end(&mut buf)?;
-            let (mapt, _): (SnippetMap, _) =
-                bincode::serde::decode_from_slice(&buf, bincode::config::standard())?;
-            map.extend(mapt);
+            let cachet = Cache::decode_as(&buf, CacheFormat::from_path(cache)).map_err(|err| {
+                anyhow::anyhow!("failed to load cache `{}`: {}", cache.display(), err)
+            })?;
+            let rebuild = sources
+                .as_ref()
+                .is_some_and(|sources| sources.can_rebuild(&cachet));
+            // Merging would define every snippet of the source config twice.
+            if !rebuild
+                && let Some(sources) = &sources
+                && cachet.header.source_config.as_deref() == Some(&sources.fingerprint())
+            {
+                anyhow::bail!(
+                    "cache `{}` is built from the source config without data to rebuild it, \
+                     use a binary cache or omit `--use-cache`",
+                    cache.display()
+                );
+            }
+            if rebuild {
+                let mut incremental = cachet.incremental;
+                caches.append(&mut incremental.caches);
+                previous.append(incremental);
+            } else {
+                caches.push((cachet.map, cache.display().to_string()));
+            }
+        }
+        // A plain cache merged into a rebuilt cache may also be given again.
+        let mut origins = BTreeSet::new();
+        caches.retain(|(_, origin)| origins.insert(origin.clone()));
+        Ok((previous, caches))
+    }
+
+    /// Merge the source config and `caches`, rebuilding only what changed since `previous`.
+    fn build(
+        &self,
+        previous: &Incremental,
+        caches: &[(SnippetMap, String)],
+    ) -> anyhow::Result<Cache> {
+        let mut header = Header::default();
+        let (mut merger, mut incremental) = if let Some(target_config) = self.load_sources()? {
+            header.format = Some(target_config.format.to_string());
+            header.source_config = Some(target_config.fingerprint());
+            let mut merger = Merger::new(self.collision.unwrap_or(target_config.collision));
+            let incremental = target_config.merge_into(&mut merger, previous)?;
+            (merger, incremental)
+        } else {
+            (
+                Merger::new(self.collision.unwrap_or_default()),
+                Default::default(),
+            )
+        };
+
+        for (mapt, origin) in caches {
+            merger.merge(mapt.clone(), origin)?;
+        }
+        if header.source_config.is_some() {
+            incremental.caches = caches.to_vec();
         }
 
-        self.cmd.execute(map)
+        Ok(Cache {
+            header,
+            map: merger.into_map(),
+            incremental,
+        })
     }
 }
 
 impl Command {
-    pub fn execute(&self, map: SnippetMap) -> anyhow::Result<()> {
+    pub fn execute(&self, cache: Cache) -> anyhow::Result<()> {
+        let Cache {
+            header,
+            map,
+            incremental,
+        } = cache;
         match self {
             Self::Cache { output } => {
-                create_recursive(output)?.write_all(&bincode::serde::encode_to_vec(
-                    &map,
-                    bincode::config::standard(),
-                )?)?;
+                let cache = Cache {
+                    header,
+                    map,
+                    incremental,
+                };
+                create_recursive(output)?
+                    .write_all(&cache.encode_as(CacheFormat::from_path(output))?)?;
             }
-            Self::List { not_hide } => {
-                let list = map.keys(!not_hide).join(" ");
+            Self::Inspect { file } => {
+                let mut buf = Vec::new();
+                File::open(file)
+                    .map_err(|err| FileNotFound(file.clone(), err))?
+                    .read_to_end(&mut buf)?;
+                let format = CacheFormat::from_path(file);
+                let header = Cache::decode_header_as(&buf, format)?;
+                let mut out = header.to_string();
+                match Cache::decode_as(&buf, format) {
+                    Ok(cache) => out.push_str(&format!(
+                        "snippets: {}\nsources: {}\n",
+                        cache.map.map.len(),
+                        cache.incremental.sources.len()
+                    )),
+                    Err(err) => eprintln!("warning: {}", err),
+                }
+                stdout().write_all(out.as_bytes())?;
+            }
+            Self::List {
+                not_hide,
+                aliases,
+                long,
+                tag,
+                category,
+            } => {
+                // pairs of listed name and snippet name
+                let mut names: Vec<(&str, &str)> = Vec::new();
+                for name in map.keys(!not_hide) {
+                    let link = &map.map[name];
+                    if tag.iter().all(|tag| link.tags.contains(tag))
+                        && (category.is_none() || link.category == *category)
+                    {
+                        names.push((name, name));
+                        if *aliases {
+                            names.extend(
+                                link.aliases
+                                    .iter()
+                                    .filter(|alias| *not_hide || !alias.starts_with('_'))
+                                    .filter(|alias| {
+                                        map.resolve(alias).is_some_and(|(to, _)| to == name)
+                                    })
+                                    .map(|alias| (alias.as_str(), name)),
+                            );
+                        }
+                    }
+                }
+                names.sort_unstable();
+                let list = if *long {
+                    names
+                        .into_iter()
+                        .map(|(listed, name)| {
+                            let description = if listed != name {
+                                Some(format!("alias of `{}`", name))
+                            } else {
+                                describe(&map.map[name])
+                            };
+                            match description {
+                                Some(description) => format!("{}\t{}\n", listed, description),
+                                None => format!("{}\n", listed),
+                            }
+                        })
+                        .collect()
+                } else {
+                    names
+                        .into_iter()
+                        .map(|(listed, _)| listed)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                };
                 stdout().write_all(list.as_bytes())?;
             }
+            Self::Search {
+                query,
+                limit,
+                not_hide,
+                format,
+            } => {
+                let result = search::execute(&map, query, *limit, !not_hide, *format)?;
+                stdout().write_all(result.as_bytes())?;
+            }
             Self::Snippet {
                 output,
                 ignore_include,
+                aliases,
             } => {
-                let snippet = to_string(&map.to_vscode(*ignore_include))?;
+                let snippet = to_string(&map.to_vscode(*ignore_include, *aliases))?;
                 match output {
                     Some(file) => create_recursive(file)?.write_all(snippet.as_bytes())?,
                     None => stdout().write_all(snippet.as_bytes())?,
                 }
             }
-            Self::Bundle { name, excludes } => {
-                let link = map
-                    .map
-                    .get(name)
+            Self::Bundle {
+                name,
+                excludes,
+                into,
+                in_place,
+                lenient,
+                order,
+            } => {
+                let (name, link) = map
+                    .resolve(name)
                     .with_context(|| format!("snippet `{}` not found", name))?;
-                let excludes = excludes.iter().map(|s| s.as_str()).collect();
-                stdout().write_all(map.bundle(name, link, excludes, true).as_bytes())?;
+                let mut excludes: BTreeSet<&str> = excludes
+                    .iter()
+                    .map(|s| map.resolve(s).map_or(s.as_str(), |(name, _)| name))
+                    .collect();
+                let mut contents = match into {
+                    Some(file) if file.as_os_str() == "-" => {
+                        if *in_place {
+                            anyhow::bail!("`--in-place` cannot be used with stdin");
+                        }
+                        let mut contents = String::new();
+                        stdin().read_to_string(&mut contents)?;
+                        Some(contents)
+                    }
+                    Some(file) => Some(
+                        std::fs::read_to_string(file)
+                            .map_err(|err| FileNotFound(file.clone(), err))?,
+                    ),
+                    None => None,
+                };
+                // Snippets already guarded in the file are neither bundled nor warned about.
+                let guards: Vec<String> = contents
+                    .iter()
+                    .flat_map(|contents| find_guards(contents))
+                    .map(ToOwned::to_owned)
+                    .collect();
+                excludes.extend(guards.iter().map(String::as_str));
+                for warning in deprecation_warnings(&map, &[name], &excludes) {
+                    eprintln!("warning: {}.", warning);
+                }
+                match (into, contents.as_mut()) {
+                    (Some(file), Some(contents)) => {
+                        map.bundle_into(contents, [name], excludes, *lenient, *order)?;
+                        if *in_place {
+                            File::create(file)?.write_all(contents.as_bytes())?;
+                        } else {
+                            stdout().write_all(contents.as_bytes())?;
+                        }
+                    }
+                    _ => {
+                        let contents = if *lenient {
+                            map.bundle(name, link, excludes, true, *order)
+                        } else {
+                            map.try_bundle(name, link, excludes, true, *order)?
+                        };
+                        stdout().write_all(contents.as_bytes())?;
+                    }
+                }
+            }
+            Self::Expand {
+                file,
+                crate_name,
+                lenient,
+                order,
+            } => {
+                stdout().write_all(
+                    expand::execute(&map, file, crate_name, *lenient, *order)?.as_bytes(),
+                )?;
+            }
+            Self::Show { name, format } => {
+                stdout().write_all(show::execute(&map, name, *format)?.as_bytes())?;
+            }
+            Self::Lsp => lsp::execute(&map)?,
+            Self::Deps { name, format } => {
+                stdout().write_all(deps::execute(&map, name.as_deref(), *format)?.as_bytes())?;
             }
             Self::Verify {
                 toolchain,
@@ -176,3 +527,150 @@ fn create_recursive<P: AsRef<Path>>(path: P) -> std::io::Result<File> {
     }
     File::create(path)
 }
+
+/// Drop locations of spans parsed so far, which proc-macro2 keeps per thread for the whole run.
+///
+/// Long-running modes call this between builds or requests, when no span is alive.
+fn invalidate_spans() {
+    proc_macro2::extra::invalidate_current_thread_spans();
+    rayon::broadcast(|_| proc_macro2::extra::invalidate_current_thread_spans());
+}
+
+/// Snippet map of `(name, contents)` pairs.
+#[cfg(test)]
+fn test_map(snippets: &[(&str, &str)]) -> SnippetMap {
+    snippets
+        .iter()
+        .map(|(name, contents)| {
+            let mut link = codesnip_core::LinkedSnippet::default();
+            link.push_contents(contents);
+            (name.to_string(), link)
+        })
+        .collect()
+}
+
+#[test]
+fn test_bundle_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("main.rs");
+    let contents = "fn main() {}\n// codesnip-guard: b\nfn b() {}";
+    std::fs::write(&file, contents).unwrap();
+    let mut map = test_map(&[
+        ("a", "fn a() {}\n"),
+        ("b", "fn b() {}\n"),
+        ("c", "fn c() {}\n"),
+    ]);
+    if let Some(link) = map.map.get_mut("a") {
+        link.push_includes(["b", "c"].map(String::from));
+    }
+    let command = Command::Bundle {
+        name: "a".to_string(),
+        excludes: Vec::new(),
+        into: Some(file.clone()),
+        in_place: true,
+        lenient: false,
+        order: BundleOrder::default(),
+    };
+    let cache = Cache {
+        header: Header::default(),
+        map,
+        incremental: Default::default(),
+    };
+    command.execute(cache).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        format!(
+            "{}\n// codesnip-guard: a\nfn a() {{}}\n// codesnip-guard: c\nfn c() {{}}\n",
+            contents
+        )
+    );
+}
+
+#[test]
+fn test_load_caches_with_source_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = |name: &str, contents: &str| {
+        let source = dir.path().join(format!("{}.rs", name));
+        std::fs::write(&source, contents).unwrap();
+        let config = dir.path().join(format!("{}.toml", name));
+        std::fs::write(
+            &config,
+            format!("[[sources]]\npath = {:?}\n", source.to_str().unwrap()),
+        )
+        .unwrap();
+        config
+    };
+    let main_config = config("main", "#[codesnip::entry]\npub fn main_fn() {}\n");
+    let other_config = config("other", "#[codesnip::entry]\npub fn other_fn() {}\n");
+    let run = |source_config: &Path, use_cache: Vec<PathBuf>, cmd: Command| {
+        let config = Config {
+            use_cache,
+            source_config: Some(source_config.to_path_buf()),
+            discovered: false,
+            collision: None,
+            watch: false,
+            cmd,
+        };
+        let (previous, caches) = config.load_caches().unwrap();
+        let cache = config.build(&previous, &caches).unwrap();
+        (previous, cache)
+    };
+    let cache_to = |source_config: &Path, name: &str| {
+        let output = dir.path().join(name);
+        let (_, cache) = run(source_config, Vec::new(), Command::Lsp);
+        Command::Cache {
+            output: output.clone(),
+        }
+        .execute(cache)
+        .unwrap();
+        output
+    };
+    let main_cache = cache_to(&main_config, "main.bin");
+    let other_cache = cache_to(&other_config, "other.bin");
+
+    let (previous, cache) = run(
+        &main_config,
+        vec![main_cache, other_cache.clone()],
+        Command::Lsp,
+    );
+    assert_eq!(previous.sources.len(), 1);
+    assert_eq!(cache.map.keys(false), ["main_fn", "other_fn"]);
+    assert_eq!(cache.incremental.sources.len(), 1);
+
+    // Snippets of plain caches merged into a cache survive rebuilding it.
+    let combined = dir.path().join("combined.bin");
+    let (_, cache) = run(&main_config, vec![other_cache.clone()], Command::Lsp);
+    assert_eq!(cache.incremental.caches.len(), 1);
+    Command::Cache {
+        output: combined.clone(),
+    }
+    .execute(cache)
+    .unwrap();
+    for use_cache in [vec![combined.clone()], vec![combined, other_cache.clone()]] {
+        let (previous, cache) = run(&main_config, use_cache, Command::Lsp);
+        assert_eq!(previous.sources.len(), 1);
+        assert_eq!(cache.map.keys(false), ["main_fn", "other_fn"]);
+        assert_eq!(cache.map.map["other_fn"].contents, "pub fn other_fn() {}\n");
+        assert_eq!(cache.incremental.caches.len(), 1);
+    }
+
+    // A text cache of the source config has no data to rebuild it and is not merged twice.
+    let text_cache = cache_to(&main_config, "main.json");
+    let config = Config {
+        use_cache: vec![text_cache.clone(), other_cache],
+        source_config: Some(main_config.clone()),
+        discovered: false,
+        collision: None,
+        watch: false,
+        cmd: Command::Lsp,
+    };
+    let error = config.load_caches().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "cache `{}` is built from the source config without data to rebuild it, use a \
+             binary cache or omit `--use-cache`",
+            text_cache.display()
+        )
+    );
+}
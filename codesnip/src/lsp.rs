@@ -0,0 +1,299 @@
+use crate::{invalidate_spans, mapping::describe, search::OutputFormat, show};
+use codesnip_core::{BundleOrder, SnippetMap, find_guards};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams,
+    CodeActionProviderCapability, CompletionItem, CompletionItemKind, CompletionItemTag,
+    CompletionOptions, CompletionParams, CompletionResponse, Documentation, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, InsertTextFormat, MarkupContent, MarkupKind, Position,
+    Range, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Uri,
+    WorkspaceEdit,
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    },
+    request::{CodeActionRequest, Completion, HoverRequest, Request as _},
+};
+use std::collections::{BTreeSet, HashMap};
+
+struct Server<'m> {
+    map: &'m SnippetMap,
+    /// Contents of open documents.
+    documents: HashMap<Uri, String>,
+}
+
+/// Serve the snippets over the language server protocol on stdio.
+pub fn execute(map: &SnippetMap) -> anyhow::Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection, map)?;
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Initialize and handle messages until shutdown.
+fn serve(connection: &Connection, map: &SnippetMap) -> anyhow::Result<()> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions::default()),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    let mut server = Server {
+        map,
+        documents: HashMap::new(),
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                invalidate_spans();
+                let response = server.handle_request(request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => server.handle_notification(notification),
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn dispatch<R: lsp_types::request::Request>(
+    request: Request,
+    f: impl FnOnce(R::Params) -> R::Result,
+) -> Response {
+    let id = request.id.clone();
+    match request.extract::<R::Params>(R::METHOD) {
+        Ok((id, params)) => Response::new_ok(id, f(params)),
+        Err(err) => Response::new_err(id, ErrorCode::InvalidParams as i32, err.to_string()),
+    }
+}
+
+fn extract<N: lsp_types::notification::Notification>(
+    notification: Notification,
+) -> Option<N::Params> {
+    notification.extract(N::METHOD).ok()
+}
+
+impl Server<'_> {
+    fn handle_request(&mut self, request: Request) -> Response {
+        match request.method.as_str() {
+            Completion::METHOD => dispatch::<Completion>(request, |params| self.completion(params)),
+            HoverRequest::METHOD => dispatch::<HoverRequest>(request, |params| self.hover(params)),
+            CodeActionRequest::METHOD => {
+                dispatch::<CodeActionRequest>(request, |params| self.code_action(params))
+            }
+            method => Response::new_err(
+                request.id.clone(),
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported method `{}`", method),
+            ),
+        }
+    }
+
+    fn handle_notification(&mut self, notification: Notification) {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                if let Some(params) = extract::<DidOpenTextDocument>(notification) {
+                    let document = params.text_document;
+                    self.documents.insert(document.uri, document.text);
+                }
+            }
+            DidChangeTextDocument::METHOD => {
+                if let Some(params) = extract::<DidChangeTextDocument>(notification)
+                    && let Some(change) = params.content_changes.into_iter().next_back()
+                {
+                    self.documents.insert(params.text_document.uri, change.text);
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                if let Some(params) = extract::<DidCloseTextDocument>(notification) {
+                    self.documents.remove(&params.text_document.uri);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Snippets not yet bundled into the document, each inserting its bundle.
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let uri = &params.text_document_position.text_document.uri;
+        let document = self.documents.get(uri).map_or("", String::as_str);
+        let guards = find_guards(document);
+        let items = self
+            .map
+            .map
+            .iter()
+            .filter(|(name, _)| !name.starts_with('_') && !guards.contains(name.as_str()))
+            .map(|(name, link)| {
+                let contents =
+                    self.map
+                        .bundle(name, link, guards.clone(), true, BundleOrder::default());
+                CompletionItem {
+                    label: name.clone(),
+                    kind: Some(CompletionItemKind::SNIPPET),
+                    detail: describe(link),
+                    documentation: Some(Documentation::MarkupContent(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: code_block("rust", &link.contents),
+                    })),
+                    tags: link
+                        .deprecated
+                        .as_ref()
+                        .map(|_| vec![CompletionItemTag::DEPRECATED]),
+                    insert_text: Some(contents),
+                    insert_text_format: Some(InsertTextFormat::PLAIN_TEXT),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        Some(CompletionResponse::Array(items))
+    }
+
+    /// Details of the snippet on a `// codesnip-guard:` line.
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params.position;
+        let document = self
+            .documents
+            .get(&params.text_document_position_params.text_document.uri)?;
+        let line = document.split('\n').nth(position.line as usize)?;
+        let name = find_guards(line).into_iter().next()?;
+        let text = show::execute(self.map, name, OutputFormat::Text).ok()?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: code_block("text", &text),
+            }),
+            range: Some(Range::new(
+                Position::new(position.line, 0),
+                Position::new(position.line, utf16_len(line)),
+            )),
+        })
+    }
+
+    /// Append includes of bundled snippets missing from the document.
+    fn code_action(&self, params: CodeActionParams) -> Option<Vec<CodeActionOrCommand>> {
+        let uri = params.text_document.uri;
+        let document = self.documents.get(&uri)?;
+        let guards = find_guards(document);
+        let missing: BTreeSet<&str> = guards
+            .iter()
+            .flat_map(|name| self.map.includes_of(name))
+            .filter(|name| !guards.contains(name))
+            .collect();
+        if missing.is_empty() {
+            return Some(Vec::new());
+        }
+        let mut new_text = self
+            .map
+            .bundle_names(
+                missing.iter().cloned(),
+                guards,
+                true,
+                true,
+                BundleOrder::default(),
+            )
+            .ok()?;
+        if !document.is_empty() && !document.ends_with('\n') {
+            new_text.insert(0, '\n');
+        }
+        let end = end_position(document);
+        let edit = TextEdit::new(Range::new(end, end), new_text);
+        let action = CodeAction {
+            title: format!(
+                "Bundle missing dependencies: {}",
+                missing.into_iter().collect::<Vec<_>>().join(", ")
+            ),
+            kind: Some(CodeActionKind::QUICKFIX),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(uri, vec![edit])])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        Some(vec![CodeActionOrCommand::CodeAction(action)])
+    }
+}
+
+fn code_block(language: &str, contents: &str) -> String {
+    let newline = if contents.ends_with('\n') { "" } else { "\n" };
+    format!("```{}\n{}{}```", language, contents, newline)
+}
+
+/// Length in UTF-16 code units, the default position encoding.
+fn utf16_len(s: &str) -> u32 {
+    s.encode_utf16().count() as u32
+}
+
+fn end_position(document: &str) -> Position {
+    let line = document.matches('\n').count() as u32;
+    let last = document.rsplit('\n').next().unwrap_or_default();
+    Position::new(line, utf16_len(last))
+}
+
+#[test]
+fn test_lsp() {
+    use serde_json::{Value, json};
+
+    let mut map = crate::test_map(&[("a", "fn a() {}\n"), ("b", "fn b() {}\n")]);
+    if let Some(link) = map.map.get_mut("b") {
+        link.push_includes(["a".to_string()]);
+    }
+    let (server, client) = Connection::memory();
+    let server = std::thread::spawn(move || serve(&server, &map));
+    let request = |id: i32, method: &str, params: Value| -> Value {
+        let request = Request::new(id.into(), method.to_string(), params);
+        client.sender.send(request.into()).unwrap();
+        match client.receiver.recv().unwrap() {
+            Message::Response(response) => {
+                assert_eq!(response.id, id.into());
+                response.response_result.unwrap()
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+    };
+    let notify = |method: &str, params: Value| {
+        let notification = Notification::new(method.to_string(), params);
+        client.sender.send(notification.into()).unwrap();
+    };
+
+    let initialized = request(1, "initialize", json!({ "capabilities": {} }));
+    assert_eq!(initialized["capabilities"]["hoverProvider"], json!(true));
+    notify("initialized", json!({}));
+    let document = json!({ "uri": "file:///main.rs" });
+    notify(
+        DidOpenTextDocument::METHOD,
+        json!({
+            "textDocument": {
+                "uri": "file:///main.rs",
+                "languageId": "rust",
+                "version": 1,
+                "text": "fn main() {}\n// codesnip-guard: a\nfn a() {}\n",
+            }
+        }),
+    );
+
+    let position = |line: u32| {
+        json!({
+            "textDocument": document,
+            "position": { "line": line, "character": 0 },
+        })
+    };
+    let completion = request(2, Completion::METHOD, position(0));
+    let items = completion.as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["label"], "b");
+    assert_eq!(items[0]["insertText"], "// codesnip-guard: b\nfn b() {}\n");
+
+    let hover = request(3, HoverRequest::METHOD, position(1));
+    let contents = hover["contents"]["value"].as_str().unwrap();
+    assert!(contents.starts_with("```text\nname: a\n"), "{}", contents);
+    assert_eq!(request(4, HoverRequest::METHOD, position(0)), Value::Null);
+
+    request(5, "shutdown", Value::Null);
+    notify("exit", Value::Null);
+    server.join().unwrap().unwrap();
+}
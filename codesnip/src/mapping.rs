@@ -1,14 +1,33 @@
-use codesnip_core::{Filter, FormatOption, SnippetMap, rustfmt_exits};
+use crate::cache::hash_bytes;
+use codesnip_core::{
+    CollisionPolicy, Filter, FormatOption, LinkedSnippet, SnippetMap, rustfmt_exits,
+};
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 use serde::Serialize;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use syn::Item;
 
 pub trait SnippetMapExt {
     fn collect_entries(&mut self, items: &[Item], filter: Filter);
-    fn format_all(&mut self, option: &FormatOption);
-    fn to_vscode(&self, ignore_include: bool) -> BTreeMap<String, VsCode>;
+    /// Format all snippets, reusing `formatted` keyed by hash of option and contents.
+    ///
+    /// Returns the formatted contents of this map in the same manner.
+    fn format_all(
+        &mut self,
+        option: &FormatOption,
+        formatted: &BTreeMap<String, String>,
+    ) -> BTreeMap<String, String>;
+    /// Snippets for VSCode, aliases are output only if `aliases`.
+    fn to_vscode(&self, ignore_include: bool, aliases: bool) -> BTreeMap<String, VsCode>;
+}
+
+/// Merges snippet maps and reports name collisions with their origins.
+#[derive(Debug, Default)]
+pub struct Merger {
+    pub map: SnippetMap,
+    pub policy: CollisionPolicy,
+    origins: BTreeMap<String, Vec<String>>,
 }
 
 #[derive(Serialize)]
@@ -16,14 +35,124 @@ pub struct VsCode {
     prefix: String,
     body: String,
     scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+/// Description with category and tags, as `(deprecated: note) description [category] #tag`.
+pub fn describe(link: &LinkedSnippet) -> Option<String> {
+    let mut parts: Vec<String> = Vec::new();
+    match link.deprecated.as_deref() {
+        Some("") => parts.push("(deprecated)".to_string()),
+        Some(note) => parts.push(format!("(deprecated: {})", note)),
+        None => {}
+    }
+    parts.extend(link.description.iter().cloned());
+    if let Some(category) = &link.category {
+        parts.push(format!("[{}]", category));
+    }
+    parts.extend(link.tags.iter().map(|tag| format!("#{}", tag)));
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" "))
+    }
+}
+
+/// Warnings for deprecated snippets bundled with `names`.
+pub fn deprecation_warnings(
+    map: &SnippetMap,
+    names: &[&str],
+    excludes: &BTreeSet<&str>,
+) -> Vec<String> {
+    map.deprecated_in(names.iter().cloned(), excludes)
+        .into_iter()
+        .map(|(name, note)| {
+            let mut message = format!("`{}` is deprecated", name);
+            if !names.contains(&name) {
+                let names: Vec<_> = names.iter().map(|name| format!("`{}`", name)).collect();
+                message.push_str(&format!(", included by {}", names.join(", ")));
+            }
+            if !note.is_empty() {
+                message.push_str(": ");
+                message.push_str(note);
+            }
+            message
+        })
+        .collect()
 }
-impl From<(String, String)> for VsCode {
-    fn from((prefix, contents): (String, String)) -> Self {
+
+impl Merger {
+    pub fn new(policy: CollisionPolicy) -> Self {
         Self {
-            prefix,
-            body: contents.replace('$', "\\$"),
-            scope: "rust".to_string(),
+            policy,
+            ..Default::default()
+        }
+    }
+    /// Merge `map` which came from `origin`, a source path or a cache file.
+    pub fn merge(&mut self, map: SnippetMap, origin: &str) -> anyhow::Result<()> {
+        let names: Vec<String> = map.map.keys().cloned().collect();
+        let collisions = self.map.merge(map, self.policy);
+        let mut errors = Vec::new();
+        for name in collisions.iter() {
+            let origins = self
+                .origins
+                .get(name)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let message = format!(
+                "`{}` is defined in {} and `{}`",
+                name,
+                origins
+                    .iter()
+                    .map(|origin| format!("`{}`", origin))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                origin
+            );
+            match self.policy {
+                CollisionPolicy::Error => errors.push(message),
+                CollisionPolicy::Warn => eprintln!("warning: {}.", message),
+                _ => {}
+            }
+        }
+        if !errors.is_empty() {
+            anyhow::bail!("snippet name collisions:\n  {}", errors.join("\n  "));
         }
+        for name in names {
+            let origins = self.origins.entry(name).or_default();
+            match self.policy {
+                CollisionPolicy::FirstWins if !origins.is_empty() => {}
+                CollisionPolicy::LastWins => *origins = vec![origin.to_string()],
+                _ => origins.push(origin.to_string()),
+            }
+        }
+        Ok(())
+    }
+    /// Report colliding aliases and replace aliases in includes.
+    pub fn into_map(mut self) -> SnippetMap {
+        for (alias, names) in self.map.alias_collisions() {
+            let names = names
+                .iter()
+                .map(|name| format!("`{}`", name))
+                .collect::<Vec<_>>();
+            if self.map.map.contains_key(alias) {
+                eprintln!(
+                    "warning: alias `{}` of {} collides with the snippet name.",
+                    alias,
+                    names.join(", ")
+                );
+            } else {
+                eprintln!(
+                    "warning: alias `{}` is declared by {}, resolved to {}.",
+                    alias,
+                    names.join(", "),
+                    names[0]
+                );
+            }
+        }
+        self.map.resolve_aliases();
+        self.map
     }
 }
 
@@ -43,10 +172,14 @@ impl SnippetMapExt for SnippetMap {
         }
         pb.finish_and_clear();
     }
-    fn format_all(&mut self, option: &FormatOption) {
-        if matches!(option, FormatOption::Rustfmt) && !rustfmt_exits() {
+    fn format_all(
+        &mut self,
+        option: &FormatOption,
+        formatted: &BTreeMap<String, String>,
+    ) -> BTreeMap<String, String> {
+        let can_format = !matches!(option, FormatOption::Rustfmt) || rustfmt_exits();
+        if !can_format {
             eprintln!("warning: rustfmt not found.");
-            return;
         }
         let pb = ProgressBar::new(self.map.len() as u64);
         pb.set_style(
@@ -56,30 +189,66 @@ impl SnippetMapExt for SnippetMap {
                 .progress_chars("=> "),
         );
         pb.set_prefix("Formatting");
-        self.map.par_iter_mut().for_each(|(name, link)| {
-            pb.set_message(name.to_owned());
-            if !link.format(option) {
-                pb.println(format!("warning: Failed to format `{}`.", name));
-            }
-            pb.inc(1);
-        });
-        pb.finish_and_clear();
-    }
-    fn to_vscode(&self, ignore_include: bool) -> BTreeMap<String, VsCode> {
-        self.map
-            .iter()
-            .map(|(name, link)| {
-                (
-                    name.as_str(),
-                    if ignore_include {
-                        link.contents.to_string()
+        let formatted = self
+            .map
+            .par_iter_mut()
+            .filter_map(|(name, link)| {
+                let key = hash_bytes(format!("{:?}\0{}", option, link.contents).as_bytes());
+                let result = if let Some(contents) = formatted.get(&key) {
+                    link.contents = contents.clone();
+                    Some((key, contents.clone()))
+                } else if can_format {
+                    pb.set_message(name.to_owned());
+                    if link.format(option) {
+                        Some((key, link.contents.clone()))
                     } else {
-                        self.bundle(name, link, Default::default(), false)
-                    },
-                )
+                        pb.println(format!("warning: Failed to format `{}`.", name));
+                        None
+                    }
+                } else {
+                    None
+                };
+                pb.inc(1);
+                result
             })
-            .filter(|(k, _)| !k.starts_with('_'))
-            .map(|(k, v)| (k.to_owned(), From::from((k.to_owned(), v))))
-            .collect::<BTreeMap<_, _>>()
+            .collect();
+        pb.finish_and_clear();
+        formatted
+    }
+    fn to_vscode(&self, ignore_include: bool, aliases: bool) -> BTreeMap<String, VsCode> {
+        let mut snippets = BTreeMap::new();
+        for (name, link) in self.map.iter() {
+            if name.starts_with('_') {
+                continue;
+            }
+            let contents = if ignore_include {
+                link.contents.to_string()
+            } else {
+                self.bundle(name, link, Default::default(), false, Default::default())
+            };
+            let body = contents.replace('$', "\\$");
+            if aliases {
+                for alias in link.aliases.iter() {
+                    if alias.starts_with('_') || self.map.contains_key(alias) {
+                        continue;
+                    }
+                    let snippet = VsCode {
+                        prefix: alias.to_owned(),
+                        body: body.clone(),
+                        scope: "rust".to_string(),
+                        description: Some(format!("alias of `{}`", name)),
+                    };
+                    snippets.entry(alias.to_owned()).or_insert(snippet);
+                }
+            }
+            let snippet = VsCode {
+                prefix: name.to_owned(),
+                body,
+                scope: "rust".to_string(),
+                description: describe(link),
+            };
+            snippets.insert(name.to_owned(), snippet);
+        }
+        snippets
     }
 }
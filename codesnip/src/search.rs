@@ -0,0 +1,263 @@
+use codesnip_core::{ItemExt as _, LinkedSnippet, SnippetMap, entry::doc_comment};
+use serde::Serialize;
+use std::{cmp::Reverse, collections::BTreeSet, fmt::Write as _, str::FromStr};
+use syn::{ImplItem, Item, ItemImpl, ItemMod};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err("expected one of [text|json]"),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub const POSSIBLE_VALUES: [&'static str; 2] = ["text", "json"];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Field {
+    Name,
+    Alias,
+    Identifier,
+    Doc,
+}
+
+#[derive(Debug, Serialize)]
+struct Hit<'s> {
+    name: &'s str,
+    score: u32,
+    /// Best matching field and its value.
+    field: Field,
+    matched: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'s str>,
+}
+
+/// Snippets matching `query`, best first.
+pub fn execute(
+    map: &SnippetMap,
+    query: &str,
+    limit: Option<usize>,
+    hide: bool,
+    format: OutputFormat,
+) -> anyhow::Result<String> {
+    let mut hits: Vec<Hit> = map
+        .map
+        .iter()
+        .filter(|(name, _)| !hide || !name.starts_with('_'))
+        .filter_map(|(name, link)| search(name, link, query))
+        .collect();
+    hits.sort_by_key(|hit| (Reverse(hit.score), hit.field, hit.name));
+    if let Some(limit) = limit {
+        hits.truncate(limit);
+    }
+    Ok(match format {
+        OutputFormat::Text => {
+            let mut s = String::new();
+            for hit in hits.iter() {
+                match hit.field {
+                    Field::Name => writeln!(s, "{}", hit.name)?,
+                    _ => writeln!(s, "{}\t({}: {})", hit.name, hit.field.as_str(), hit.matched)?,
+                }
+            }
+            s
+        }
+        OutputFormat::Json => serde_json::to_string(&hits)?,
+    })
+}
+
+fn search<'s>(name: &'s str, link: &'s LinkedSnippet, query: &str) -> Option<Hit<'s>> {
+    let (idents, docs) = definitions(&link.contents);
+    let candidates = std::iter::once((Field::Name, name.to_string()))
+        .chain(link.aliases.iter().map(|s| (Field::Alias, s.clone())))
+        .chain(idents.into_iter().map(|s| (Field::Identifier, s)))
+        .chain(link.description.iter().map(|s| (Field::Doc, s.clone())))
+        .chain(docs.into_iter().map(|s| (Field::Doc, s)));
+    candidates
+        .filter_map(|(field, value)| {
+            let score = match field {
+                Field::Doc => substring_score(query, &value)? / 2,
+                _ => fuzzy_score(query, &value)? * field.weight() / 10,
+            };
+            Some((score, Reverse(field), value))
+        })
+        .max()
+        .map(|(score, Reverse(field), matched)| Hit {
+            name,
+            score,
+            field,
+            matched,
+            description: link.description.as_deref(),
+        })
+}
+
+impl Field {
+    fn weight(self) -> u32 {
+        match self {
+            Field::Name => 10,
+            Field::Alias => 9,
+            Field::Identifier => 8,
+            Field::Doc => 5,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Field::Name => "name",
+            Field::Alias => "alias",
+            Field::Identifier => "identifier",
+            Field::Doc => "doc",
+        }
+    }
+}
+
+/// Score of `target` containing the characters of `query` in order, ignoring case.
+///
+/// Exact, prefix and substring matches rank above scattered ones.
+fn fuzzy_score(query: &str, target: &str) -> Option<u32> {
+    let query = query.to_lowercase();
+    let target = target.to_lowercase();
+    if query.is_empty() {
+        return None;
+    }
+    if target == query {
+        return Some(1000);
+    }
+    if let Some(score) = substring_score(&query, &target) {
+        return Some(score);
+    }
+    let mut target_chars = target.char_indices();
+    let mut gaps = 0;
+    let mut last = None;
+    for ch in query.chars() {
+        let (i, _) = target_chars.find(|&(_, c)| c == ch)?;
+        if last.is_some_and(|last| last + 1 != i) {
+            gaps += 1;
+        }
+        last = Some(i);
+    }
+    Some(
+        400u32
+            .saturating_sub(gaps * 20 + target.len() as u32)
+            .max(1),
+    )
+}
+
+/// Score of `target` containing `query`, ignoring case.
+fn substring_score(query: &str, target: &str) -> Option<u32> {
+    let query = query.to_lowercase();
+    let target = target.to_lowercase();
+    if query.is_empty() {
+        return None;
+    }
+    let position = target.find(&query)? as u32;
+    let rest = (target.len() - query.len()) as u32;
+    Some(if position == 0 {
+        800u32.saturating_sub(rest).max(600)
+    } else {
+        600u32.saturating_sub(position + rest).max(400)
+    })
+}
+
+/// Identifiers of items defined in `contents`, and their doc comments.
+fn definitions(contents: &str) -> (BTreeSet<String>, Vec<String>) {
+    fn visit(items: &[Item], idents: &mut BTreeSet<String>, docs: &mut Vec<String>) {
+        for item in items {
+            if let Some(name) = item.get_default_name()
+                && !matches!(item, Item::ExternCrate(_))
+            {
+                idents.insert(name);
+            }
+            if let Some(attrs) = item.get_attributes() {
+                docs.extend(doc_comment(attrs));
+            }
+            match item {
+                Item::Mod(ItemMod {
+                    content: Some((_, items)),
+                    ..
+                }) => visit(items, idents, docs),
+                Item::Impl(ItemImpl { items, .. }) => {
+                    for item in items {
+                        let (ident, attrs) = match item {
+                            ImplItem::Fn(item) => (&item.sig.ident, &item.attrs),
+                            ImplItem::Const(item) => (&item.ident, &item.attrs),
+                            ImplItem::Type(item) => (&item.ident, &item.attrs),
+                            _ => continue,
+                        };
+                        idents.insert(ident.to_string());
+                        docs.extend(doc_comment(attrs));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut idents = BTreeSet::new();
+    let mut docs = Vec::new();
+    if let Ok(file) = syn::parse_file(contents) {
+        docs.extend(doc_comment(&file.attrs));
+        visit(&file.items, &mut idents, &mut docs);
+    }
+    (idents, docs)
+}
+
+#[test]
+fn test_fuzzy_score() {
+    for (query, target, score) in [
+        ("gcd", "gcd", Some(1000)),
+        ("GCD", "gcd", Some(1000)),
+        ("gc", "gcd", Some(799)),
+        ("cd", "gcd", Some(598)),
+        ("gd", "gcd", Some(377)),
+        ("mdnt", "modint", Some(354)),
+        ("dg", "gcd", None),
+        ("", "gcd", None),
+    ] {
+        assert_eq!(fuzzy_score(query, target), score, "{} in {}", query, target);
+    }
+}
+
+#[test]
+fn test_search_ranking() {
+    let mut map = crate::test_map(&[
+        ("gcd", "pub fn gcd() {}"),
+        ("gcd_ext", "pub fn ext_gcd() {}"),
+        (
+            "lcm",
+            "/// Least common multiple using gcd.\npub fn lcm() {}",
+        ),
+        ("math", "pub fn great_common_divisor() {}"),
+        ("modint", "pub struct ModInt;"),
+        ("_gcd_impl", "pub fn gcd_impl() {}"),
+    ]);
+    if let Some(link) = map.map.get_mut("math") {
+        link.aliases.insert("gcd_alias".to_string());
+    }
+    let search = |query: &str, hide: bool| {
+        execute(&map, query, None, hide, OutputFormat::Text)
+            .unwrap()
+            .lines()
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(search("gcd", true), ["gcd", "gcd_ext", "math", "lcm"]);
+    assert_eq!(
+        search("gcd", false),
+        ["gcd", "gcd_ext", "math", "_gcd_impl", "lcm"]
+    );
+    assert_eq!(search("mint", true), ["modint"]);
+    assert!(search("xyz", true).is_empty());
+}
@@ -0,0 +1,100 @@
+use crate::search::OutputFormat;
+use anyhow::Context as _;
+use codesnip_core::{Provenance, SnippetMap, minify};
+use serde::Serialize;
+use std::{collections::BTreeSet, fmt::Write as _};
+
+#[derive(Serialize)]
+struct Show<'s> {
+    name: &'s str,
+    hidden: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'s str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deprecated: Option<&'s str>,
+    aliases: &'s BTreeSet<String>,
+    provenance: &'s [Provenance],
+    includes: &'s BTreeSet<String>,
+    /// Direct and indirect includes.
+    transitive_includes: BTreeSet<&'s str>,
+    included_by: BTreeSet<&'s str>,
+    size: usize,
+    /// `None` if the contents cannot be minified.
+    minified_size: Option<usize>,
+    contents: &'s str,
+}
+
+/// Show details of the snippet `name`.
+pub fn execute(map: &SnippetMap, name: &str, format: OutputFormat) -> anyhow::Result<String> {
+    let (name, link) = map
+        .resolve(name)
+        .with_context(|| format!("snippet `{}` not found", name))?;
+    let show = Show {
+        name,
+        hidden: name.starts_with('_'),
+        description: link.description.as_deref(),
+        deprecated: link.deprecated.as_deref(),
+        aliases: &link.aliases,
+        provenance: &link.provenance,
+        includes: &link.includes,
+        transitive_includes: map.includes_of(name),
+        included_by: map.included_by(name),
+        size: link.contents.len(),
+        minified_size: minify(&link.contents).map(|minified| minified.len()),
+        contents: &link.contents,
+    };
+    Ok(match format {
+        OutputFormat::Text => to_text(&show)?,
+        OutputFormat::Json => serde_json::to_string(&show)?,
+    })
+}
+
+fn to_text(show: &Show) -> anyhow::Result<String> {
+    fn join<'a>(names: impl IntoIterator<Item = &'a str>) -> String {
+        let names: Vec<&str> = names.into_iter().collect();
+        if names.is_empty() {
+            "-".to_string()
+        } else {
+            names.join(" ")
+        }
+    }
+
+    let mut s = String::new();
+    writeln!(s, "name: {}", show.name)?;
+    writeln!(s, "hidden: {}", show.hidden)?;
+    if let Some(description) = show.description {
+        writeln!(s, "description: {}", description)?;
+    }
+    if let Some(note) = show.deprecated {
+        writeln!(
+            s,
+            "deprecated: {}",
+            if note.is_empty() { "yes" } else { note }
+        )?;
+    }
+    writeln!(
+        s,
+        "aliases: {}",
+        join(show.aliases.iter().map(|s| s.as_str()))
+    )?;
+    let sources: Vec<String> = show.provenance.iter().map(|p| p.to_string()).collect();
+    writeln!(s, "source: {}", join(sources.iter().map(|s| s.as_str())))?;
+    writeln!(
+        s,
+        "includes: {}",
+        join(show.includes.iter().map(|s| s.as_str()))
+    )?;
+    writeln!(
+        s,
+        "transitive includes: {}",
+        join(show.transitive_includes.iter().cloned())
+    )?;
+    writeln!(s, "included by: {}", join(show.included_by.iter().cloned()))?;
+    match show.minified_size {
+        Some(minified) => writeln!(s, "size: {} bytes ({} bytes minified)", show.size, minified)?,
+        None => writeln!(s, "size: {} bytes", show.size)?,
+    }
+    writeln!(s)?;
+    s.push_str(show.contents);
+    Ok(s)
+}
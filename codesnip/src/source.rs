@@ -1,12 +1,22 @@
-use crate::mapping::SnippetMapExt as _;
-use codesnip_core::{Filter, FormatOption, SnippetMap, parse_file_recursive};
+use crate::{
+    cache::{Cache, Incremental, SourceCache, hash_bytes},
+    mapping::{Merger, SnippetMapExt as _},
+};
+use anyhow::Context as _;
+use cargo_metadata::{MetadataCommand, Target};
+use codesnip_core::{
+    CfgSet, CollisionPolicy, Error::FileNotFound, Filter, FormatOption, IncludeMode, SnippetMap,
+    parse_file_recursive_with_files, prefixed_name,
+};
 use git2::build::RepoBuilder;
 use serde::{Deserialize, Deserializer};
 use serde_with::{DeserializeAs, DisplayFromStr, serde_as};
 use std::{
-    fmt,
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Write as _},
     marker::PhantomData,
     path::{Path, PathBuf},
+    process::Command,
 };
 use syn::parse_str;
 use tempfile::{TempDir, tempdir};
@@ -23,6 +33,8 @@ pub struct Sources {
     #[serde_as(as = "Option<Vec<SynParse>>")]
     pub cfg_disable: Option<Vec<syn::Meta>>,
     #[serde(default)]
+    pub cfg_target: Option<CfgTarget>,
+    #[serde(default)]
     #[serde_as(as = "Option<Vec<SynParse>>")]
     pub filter_attr: Option<Vec<syn::Path>>,
     #[serde(default)]
@@ -31,12 +43,38 @@ pub struct Sources {
     #[serde(default)]
     #[serde_as(as = "DisplayFromStr")]
     pub format: FormatOption,
+    #[serde(default)]
+    pub preserve_source: bool,
+    #[serde(default)]
+    #[serde_as(as = "DisplayFromStr")]
+    pub include_mode: IncludeMode,
+    #[serde(default)]
+    #[serde_as(as = "DisplayFromStr")]
+    pub collision: CollisionPolicy,
+    /// Directory relative paths of local sources are resolved from, the current directory if
+    /// empty.
+    #[serde(skip)]
+    pub dir: PathBuf,
 }
 
+/// Names of config files looked up in each directory, in order of priority.
+const CONFIG_FILES: [&str; 3] = ["codesnip.toml", "codesnip.json", ".codesnip.toml"];
+
 #[serde_as]
 #[derive(Debug, Deserialize)]
 pub struct Source {
-    pub path: PathBuf,
+    /// Root file, or a glob pattern whose matched files are each parsed as a root.
+    pub path: Option<PathBuf>,
+    /// Glob patterns of files not to take from `path`.
+    pub exclude: Option<Vec<String>>,
+    /// Prefix snippets of each root file with its file stem, after `prefix`.
+    pub stem_prefix: Option<bool>,
+    /// Package in the workspace whose library target is the root file.
+    pub package: Option<String>,
+    /// Manifest of the workspace to look up `package` or `workspace` in.
+    pub manifest_path: Option<PathBuf>,
+    /// Take library targets of all workspace members, each prefixed with its name.
+    pub workspace: Option<bool>,
     pub prefix: Option<String>,
     pub git: Option<GitHubSource>,
     #[serde_as(as = "Option<Vec<SynParse>>")]
@@ -44,10 +82,31 @@ pub struct Source {
     pub cfg_enable: Option<Vec<syn::Meta>>,
     #[serde_as(as = "Option<Vec<SynParse>>")]
     pub cfg_disable: Option<Vec<syn::Meta>>,
+    pub cfg_target: Option<CfgTarget>,
+    /// Cargo features to enable, resolved with the `[features]` table of the crate's manifest.
+    pub features: Option<Vec<String>>,
+    pub all_features: Option<bool>,
+    pub default_features: Option<bool>,
     #[serde_as(as = "Option<Vec<SynParse>>")]
     pub filter_attr: Option<Vec<syn::Path>>,
     #[serde_as(as = "Option<Vec<SynParse>>")]
     pub filter_item: Option<Vec<syn::Path>>,
+    pub preserve_source: Option<bool>,
+    #[serde(default)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub include_mode: Option<IncludeMode>,
+}
+
+/// Where to get the options set for the target, `cfg_enable` and `cfg_disable` override them.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CfgTarget {
+    /// Target triple for `rustc --print cfg`, the host if not specified.
+    pub target: Option<String>,
+    /// Toolchain for `rustc --print cfg`.
+    pub toolchain: Option<String>,
+    /// Options saved from `rustc --print cfg` instead of running it.
+    pub file: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -99,48 +158,346 @@ where
 }
 
 impl Sources {
+    /// Load a config file, or `[package.metadata.codesnip]` or `[workspace.metadata.codesnip]`
+    /// of a `Cargo.toml`.
     pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
         enum SerializeType {
             Json,
             Toml,
+            Manifest,
         }
-        let ty = match path.as_ref().extension() {
+        let path = path.as_ref();
+        let ty = match path.extension() {
+            _ if path.file_name().is_some_and(|name| name == "Cargo.toml") => {
+                SerializeType::Manifest
+            }
             Some(ext) if ext == "json" => SerializeType::Json,
             Some(ext) if ext == "toml" => SerializeType::Toml,
             _ => return Err(anyhow::anyhow!("Invalid file extension")),
         };
-        let sources: Self = match ty {
+        let sources = match ty {
             SerializeType::Json => {
                 let file = std::fs::File::open(path)?;
                 let reader = std::io::BufReader::new(file);
                 serde_json::from_reader(reader)?
             }
             SerializeType::Toml => toml::from_str(&std::fs::read_to_string(path)?)?,
+            SerializeType::Manifest => Self::from_manifest(&std::fs::read_to_string(path)?)?
+                .with_context(|| format!("no `metadata.codesnip` in `{}`", path.display()))?,
         };
         Ok(sources)
     }
+    /// Load a config found by [`Sources::discover`], whose relative paths are resolved from its
+    /// directory instead of the current directory.
+    pub fn load_discovered(path: &Path) -> anyhow::Result<Self> {
+        let mut sources = Self::load(path)?;
+        sources.dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        Ok(sources)
+    }
+    fn from_manifest(manifest: &str) -> anyhow::Result<Option<Self>> {
+        let manifest: toml::Table = toml::from_str(manifest)?;
+        for key in ["package", "workspace"] {
+            if let Some(config) = manifest
+                .get(key)
+                .and_then(|table| table.get("metadata"))
+                .and_then(|metadata| metadata.get("codesnip"))
+            {
+                return Ok(Some(config.clone().try_into()?));
+            }
+        }
+        Ok(None)
+    }
+    /// Find a config file in `dir` or its nearest parent, or a `Cargo.toml` with a config in its
+    /// metadata.
+    pub fn discover(dir: &Path) -> anyhow::Result<PathBuf> {
+        for dir in dir.ancestors() {
+            for name in CONFIG_FILES {
+                let path = dir.join(name);
+                if path.is_file() {
+                    return Ok(path);
+                }
+            }
+            let manifest = dir.join("Cargo.toml");
+            if let Ok(contents) = std::fs::read_to_string(&manifest)
+                && Self::from_manifest(&contents).is_ok_and(|config| config.is_some())
+            {
+                return Ok(manifest);
+            }
+        }
+        anyhow::bail!(
+            "no source config found in `{}` or its parents, looked for {} and \
+             `[package.metadata.codesnip]` or `[workspace.metadata.codesnip]` in `Cargo.toml`; \
+             give one by `--source-config` or use `--use-cache`",
+            dir.display(),
+            CONFIG_FILES.map(|name| format!("`{}`", name)).join(", ")
+        )
+    }
     pub fn snippet_map(&self) -> anyhow::Result<SnippetMap> {
-        let mut map = SnippetMap::new();
+        let mut merger = Merger::new(self.collision);
+        self.merge_into(&mut merger, &Default::default())?;
+        Ok(merger.into_map())
+    }
+    /// Hash of the settings of all sources and the format option.
+    pub fn fingerprint(&self) -> String {
+        let mut s = String::new();
         for source in &self.sources {
-            map.extend(source.snippet_map(self)?);
+            let _ = writeln!(s, "source: {}", source.fingerprint(self));
+        }
+        let _ = writeln!(s, "format: {}", self.format);
+        let _ = writeln!(s, "collision: {:?}", self.collision);
+        hash_bytes(s.as_bytes())
+    }
+    /// Merge snippets of all sources into `merger` and format them.
+    ///
+    /// Sources whose files are unchanged since `previous` are not parsed again, and snippets
+    /// whose contents are unchanged are not formatted again. Returns the results to reuse next time.
+    ///
+    /// Expected to be called before merging already formatted snippets.
+    pub fn merge_into(
+        &self,
+        merger: &mut Merger,
+        previous: &Incremental,
+    ) -> anyhow::Result<Incremental> {
+        let mut incremental = Incremental::default();
+        for source in &self.sources {
+            let fingerprint = source.fingerprint(self);
+            let cache = source.snippet_map(self, previous.sources.get(&fingerprint))?;
+            merger.merge(cache.map.clone(), &source.origin())?;
+            incremental.sources.insert(fingerprint, cache);
+        }
+        incremental.formatted = merger.map.format_all(&self.format, &previous.formatted);
+        Ok(incremental)
+    }
+    /// Whether `cache` was built from this config or only from sources in it, so that it is
+    /// rebuilt incrementally instead of merged like a plain cache.
+    pub fn can_rebuild(&self, cache: &Cache) -> bool {
+        if cache.incremental.sources.is_empty() {
+            return false;
+        }
+        if cache.header.source_config.as_deref() == Some(self.fingerprint().as_str()) {
+            return true;
+        }
+        let fingerprints: BTreeSet<String> = self
+            .sources
+            .iter()
+            .map(|source| source.fingerprint(self))
+            .collect();
+        cache
+            .incremental
+            .sources
+            .keys()
+            .all(|fingerprint| fingerprints.contains(fingerprint))
+    }
+    /// Root files of local sources resolved again, and directories to watch recursively for new
+    /// root files and modules.
+    pub fn local_roots(&self) -> anyhow::Result<(BTreeSet<PathBuf>, BTreeSet<PathBuf>)> {
+        let mut roots = BTreeSet::new();
+        let mut dirs = BTreeSet::new();
+        for source in self.sources.iter().filter(|source| source.git.is_none()) {
+            let (source_roots, _) = source.roots(&self.dir)?;
+            let source_roots: Vec<PathBuf> = source_roots
+                .into_iter()
+                .map(|root| self.dir.join(root.path))
+                .collect();
+            match &source.path {
+                Some(path) if is_glob(path) => {
+                    dirs.insert(self.dir.join(glob_dir(path)));
+                }
+                _ => dirs.extend(
+                    source_roots
+                        .iter()
+                        .filter_map(|root| root.parent())
+                        .map(Path::to_path_buf),
+                ),
+            }
+            roots.extend(source_roots);
+        }
+        Ok((roots, dirs))
+    }
+    /// Root files of local sources and the files read from them in `incremental`.
+    pub fn local_files(&self, incremental: &Incremental) -> BTreeSet<PathBuf> {
+        let mut files = BTreeSet::new();
+        for source in self.sources.iter().filter(|source| source.git.is_none()) {
+            let path = source.path.as_ref().filter(|path| !is_glob(path));
+            let cache = incremental.sources.get(&source.fingerprint(self));
+            let cached = cache.into_iter().flat_map(|cache| cache.files.keys());
+            files.extend(
+                path.into_iter()
+                    .chain(&source.manifest_path)
+                    .chain(cached)
+                    .map(|path| self.dir.join(path)),
+            );
         }
-        map.format_all(&self.format);
-        Ok(map)
+        files
     }
 }
 
 impl Source {
-    fn snippet_map(&self, sources: &Sources) -> anyhow::Result<SnippetMap> {
-        let (guard, path) = if let Some(git_source) = self.git.as_ref() {
-            let dir = git_source.prepare()?;
-            let path = dir.path().join(&self.path);
-            (Some(dir), path)
+    fn origin(&self) -> String {
+        let location = match (&self.path, &self.package) {
+            (Some(path), _) => path.display().to_string(),
+            (None, Some(package)) => format!("package:{}", package),
+            (None, None) => "workspace".to_string(),
+        };
+        match &self.git {
+            Some(git) => format!("{}:{}", git.url, location),
+            None => location,
+        }
+    }
+
+    /// Hash of the settings which affect parsing of this source.
+    fn fingerprint(&self, sources: &Sources) -> String {
+        fn tokens<T: quote::ToTokens>(list: Option<&Vec<T>>) -> String {
+            list.into_iter()
+                .flatten()
+                .map(|t| t.to_token_stream().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+
+        let mut s = String::new();
+        let _ = writeln!(
+            s,
+            "path: {:?} {:?} {:?}",
+            self.path, self.exclude, self.stem_prefix
+        );
+        let _ = writeln!(
+            s,
+            "package: {:?} {:?} {:?}",
+            self.package, self.manifest_path, self.workspace
+        );
+        let _ = writeln!(s, "prefix: {:?}", self.prefix);
+        if let Some(git) = &self.git {
+            let _ = writeln!(s, "git: {} {:?}", git.url, git.dependency);
+        }
+        let _ = writeln!(
+            s,
+            "cfg_enable: {}",
+            tokens(self.cfg_enable.as_ref().or(sources.cfg_enable.as_ref()))
+        );
+        let _ = writeln!(
+            s,
+            "cfg_disable: {}",
+            tokens(self.cfg_disable.as_ref().or(sources.cfg_disable.as_ref()))
+        );
+        let cfg_target = self.cfg_target.as_ref().or(sources.cfg_target.as_ref());
+        let _ = writeln!(s, "cfg_target: {:?}", cfg_target);
+        if let Some(file) = cfg_target.and_then(|cfg_target| cfg_target.file.as_ref())
+            && let Ok(contents) = std::fs::read(sources.dir.join(file))
+        {
+            let _ = writeln!(s, "cfg_target file: {}", hash_bytes(&contents));
+        }
+        let _ = writeln!(
+            s,
+            "features: {:?} {:?} {:?}",
+            self.features, self.all_features, self.default_features
+        );
+        let _ = writeln!(
+            s,
+            "filter_attr: {}",
+            tokens(self.filter_attr.as_ref().or(sources.filter_attr.as_ref()))
+        );
+        let _ = writeln!(
+            s,
+            "filter_item: {}",
+            tokens(self.filter_item.as_ref().or(sources.filter_item.as_ref()))
+        );
+        let _ = writeln!(
+            s,
+            "preserve_source: {}",
+            self.preserve_source.unwrap_or(sources.preserve_source)
+        );
+        let _ = writeln!(
+            s,
+            "include_mode: {:?}",
+            self.include_mode.unwrap_or(sources.include_mode)
+        );
+        hash_bytes(s.as_bytes())
+    }
+
+    /// Manifest of the crate containing `path` and the features enabled in it, `None` unless
+    /// `features`, `all_features` or `default_features` is given.
+    fn enabled_features(&self, path: &Path) -> anyhow::Result<Option<(PathBuf, BTreeSet<String>)>> {
+        if self.features.is_none() && self.all_features.is_none() && self.default_features.is_none()
+        {
+            return Ok(None);
+        }
+        let path = path
+            .canonicalize()
+            .map_err(|err| FileNotFound(path.to_path_buf(), err))?;
+        let manifest = path
+            .ancestors()
+            .skip(1)
+            .map(|dir| dir.join("Cargo.toml"))
+            .find(|manifest| manifest.is_file())
+            .with_context(|| format!("`Cargo.toml` of `{}` not found", path.display()))?;
+        let metadata = MetadataCommand::new()
+            .manifest_path(&manifest)
+            .no_deps()
+            .exec()?;
+        let package = metadata
+            .packages
+            .iter()
+            .find(|package| package.manifest_path == manifest)
+            .with_context(|| format!("no package in `{}`", manifest.display()))?;
+
+        let mut requested = self.features.clone().unwrap_or_default();
+        for feature in requested.iter() {
+            if !package.features.contains_key(feature) {
+                anyhow::bail!(
+                    "feature `{}` not found in `{}`",
+                    feature,
+                    manifest.display()
+                );
+            }
+        }
+        if self.all_features.unwrap_or_default() {
+            requested.extend(package.features.keys().cloned());
+        }
+        if self.default_features.unwrap_or(true) {
+            requested.push("default".to_string());
+        }
+        let features = feature_closure(&package.features, requested);
+        Ok(Some((manifest, features)))
+    }
+
+    /// Parse snippets of this source, or reuse `cache` if no file has changed.
+    fn snippet_map(
+        &self,
+        sources: &Sources,
+        cache: Option<&SourceCache>,
+    ) -> anyhow::Result<SourceCache> {
+        let (guard, base, commit) = if let Some(git_source) = self.git.as_ref() {
+            let (dir, commit) = git_source.prepare()?;
+            let base = dir.path().to_path_buf();
+            (Some(dir), base, Some(commit))
         } else {
-            (None, self.path.clone())
+            (None, sources.dir.clone(), None)
         };
+        let origin = self.origin();
+        let set_origin = |map: &mut SnippetMap| {
+            for provenance in map.map.values_mut().flat_map(|link| &mut link.provenance) {
+                provenance.source = Some(origin.clone());
+                provenance.commit.clone_from(&commit);
+            }
+        };
+        // Files newly matched by a glob pattern are not in the cache.
+        let matched = match &self.path {
+            Some(path) if is_glob(path) => self.path_roots(path, &base)?,
+            _ => Vec::new(),
+        };
+        if let Some(cache) = cache
+            && cache.is_fresh(&base)
+            && matched
+                .iter()
+                .all(|root| cache.files.contains_key(&root.path))
+        {
+            let mut cache = cache.clone();
+            set_origin(&mut cache.map);
+            return Ok(cache);
+        }
+        let (roots, manifests) = self.roots(&base)?;
 
-        let mut map = SnippetMap::new();
-        let mut items = Vec::new();
         let cfg_enable_default = Vec::new();
         let cfg_disable_default = Vec::new();
         let cfg_enable = self
@@ -153,9 +510,10 @@ impl Source {
             .as_ref()
             .or(sources.cfg_disable.as_ref())
             .unwrap_or(&cfg_disable_default);
-        items.append(&mut parse_file_recursive(path, cfg_enable, cfg_disable)?.items);
-        drop(guard);
-
+        let mut cfg = CfgSet::new(cfg_enable, cfg_disable);
+        if let Some(cfg_target) = self.cfg_target.as_ref().or(sources.cfg_target.as_ref()) {
+            cfg = cfg.target(cfg_target.load(&sources.dir)?);
+        }
         let filter = vec![];
         let filter = Filter::new(
             self.filter_attr
@@ -166,22 +524,302 @@ impl Source {
                 .as_ref()
                 .or(sources.filter_item.as_ref())
                 .unwrap_or(&filter),
-        );
-        map.collect_entries(&items, filter);
+        )
+        .preserve_source(self.preserve_source.unwrap_or(sources.preserve_source));
+        let include_mode = self.include_mode.unwrap_or(sources.include_mode);
 
-        if let Some(prefix) = &self.prefix {
-            map = map
-                .into_iter()
-                .map(|(k, v)| (format!("{}_{}", prefix, k), v))
-                .collect();
+        let mut files = BTreeMap::new();
+        for manifest in manifests {
+            let hash = hash_bytes(&std::fs::read(base.join(&manifest))?);
+            files.insert(manifest, hash);
+        }
+        // Roots are merged like sources, snippets of a root may include ones of another by prefix.
+        let mut merger = Merger::new(sources.collision);
+        for root in roots {
+            let path = base.join(&root.path);
+            let mut cfg = cfg.clone();
+            let manifest = match self.enabled_features(&path)? {
+                Some((manifest, features)) => {
+                    cfg = cfg.features(features);
+                    Some(manifest)
+                }
+                None => None,
+            };
+            let (file, source_files) = parse_file_recursive_with_files(path, &cfg)?;
+            let items = file.items;
+            for path in source_files.paths().chain(manifest.as_deref()) {
+                let hash = hash_bytes(&std::fs::read(path)?);
+                let path = path.strip_prefix(&base).unwrap_or(path).to_path_buf();
+                files.insert(path, hash);
+            }
+
+            let filter = filter.source_files(&source_files);
+            let mut map = SnippetMap::new();
+            map.collect_entries(&items, filter);
+            for provenance in map.map.values_mut().flat_map(|link| &mut link.provenance) {
+                if let Ok(path) = provenance.path.strip_prefix(&base) {
+                    provenance.path = path.to_path_buf();
+                }
+            }
+            set_origin(&mut map);
+            for conflict in map.infer_includes(&items, filter, include_mode) {
+                for include in conflict.undeclared.iter() {
+                    eprintln!(
+                        "warning: `{}` uses `{}` but does not include it.",
+                        conflict.name, include
+                    );
+                }
+                for include in conflict.unused.iter() {
+                    eprintln!(
+                        "warning: `{}` includes `{}` but does not use it.",
+                        conflict.name, include
+                    );
+                }
+            }
+            merger.merge(
+                map.prefixed(root.prefix.as_deref()),
+                &root.path.display().to_string(),
+            )?;
+        }
+        drop(guard);
+
+        Ok(SourceCache {
+            files,
+            map: merger.map,
+        })
+    }
+
+    /// Root files to parse relative to `base` and the manifests read to find them.
+    fn roots(&self, base: &Path) -> anyhow::Result<(Vec<Root>, Vec<PathBuf>)> {
+        match (
+            &self.path,
+            &self.package,
+            self.workspace.unwrap_or_default(),
+        ) {
+            (Some(path), None, false) => {
+                if self.manifest_path.is_some() {
+                    anyhow::bail!("`manifest_path` requires `package` or `workspace`");
+                }
+                Ok((self.path_roots(path, base)?, Vec::new()))
+            }
+            (None, Some(_), false) | (None, None, true) => {
+                if self.exclude.is_some() {
+                    anyhow::bail!("`exclude` requires `path`");
+                }
+                self.package_roots(base)
+            }
+            _ => {
+                anyhow::bail!("a source requires exactly one of `path`, `package` and `workspace`")
+            }
+        }
+    }
+
+    /// Files matched by `path` relative to `base` except `exclude`, or `path` itself unless it
+    /// is a glob pattern.
+    fn path_roots(&self, path: &Path, base: &Path) -> anyhow::Result<Vec<Root>> {
+        let paths = if is_glob(path) {
+            // `base` is matched literally, an absolute pattern replaces it.
+            let pattern = Path::new(&glob::Pattern::escape(&base.to_string_lossy())).join(path);
+            let pattern = pattern
+                .to_str()
+                .with_context(|| format!("invalid glob pattern `{}`", path.display()))?;
+            let exclude = self
+                .exclude
+                .iter()
+                .flatten()
+                .map(|exclude| {
+                    glob::Pattern::new(exclude).map_err(|err| {
+                        anyhow::anyhow!("invalid glob pattern `{}`: {}", exclude, err)
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let options = glob::MatchOptions {
+                require_literal_separator: true,
+                ..Default::default()
+            };
+            let mut paths = Vec::new();
+            for matched in glob::glob(pattern)
+                .map_err(|err| anyhow::anyhow!("invalid glob pattern `{}`: {}", pattern, err))?
+            {
+                let matched = matched?;
+                if !matched.is_file() {
+                    continue;
+                }
+                let matched = matched.strip_prefix(base).unwrap_or(&matched).to_path_buf();
+                if !exclude
+                    .iter()
+                    .any(|exclude| exclude.matches_path_with(&matched, options))
+                {
+                    paths.push(matched);
+                }
+            }
+            if paths.is_empty() {
+                anyhow::bail!("no file matches `{}`", path.display());
+            }
+            paths
+        } else {
+            vec![path.to_path_buf()]
+        };
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let prefix = match path.file_stem() {
+                    Some(stem) if self.stem_prefix.unwrap_or_default() => Some(prefixed_name(
+                        self.prefix.as_deref(),
+                        &stem.to_string_lossy(),
+                    )),
+                    _ => self.prefix.clone(),
+                };
+                Root { path, prefix }
+            })
+            .collect())
+    }
+
+    /// Library targets of `package` or of all workspace members found by `cargo metadata`.
+    fn package_roots(&self, base: &Path) -> anyhow::Result<(Vec<Root>, Vec<PathBuf>)> {
+        let mut command = MetadataCommand::new();
+        command.no_deps();
+        // Paths are kept relative like `path`, so that the cache does not depend on checkouts.
+        let dir = std::env::current_dir()?.join(base);
+        if let Some(manifest_path) = &self.manifest_path {
+            command.manifest_path(base.join(manifest_path));
+        } else {
+            command.current_dir(&dir);
+        }
+        let metadata = command.exec()?;
+        let relative = |path: &Path| path.strip_prefix(&dir).unwrap_or(path).to_path_buf();
+
+        let mut packages = metadata.workspace_packages();
+        if let Some(name) = &self.package {
+            packages.retain(|package| package.name.as_str() == name);
+            if packages.is_empty() {
+                anyhow::bail!(
+                    "package `{}` not found in workspace `{}`",
+                    name,
+                    metadata.workspace_root
+                );
+            }
+        }
+        let mut roots = Vec::new();
+        let mut manifests = vec![relative(
+            metadata.workspace_root.join("Cargo.toml").as_std_path(),
+        )];
+        for package in packages {
+            let Some(lib) = package.targets.iter().find(|target| is_library(target)) else {
+                if self.package.is_some() {
+                    anyhow::bail!("package `{}` has no library target", package.name);
+                }
+                continue;
+            };
+            let prefix = match &self.package {
+                Some(_) => self.prefix.clone(),
+                None => Some(prefixed_name(self.prefix.as_deref(), &lib.name)),
+            };
+            roots.push(Root {
+                path: relative(lib.src_path.as_std_path()),
+                prefix,
+            });
+            manifests.push(relative(package.manifest_path.as_std_path()));
+        }
+        Ok((roots, manifests))
+    }
+}
+
+/// Root file of a source and the prefix of snippets in it.
+struct Root {
+    path: PathBuf,
+    prefix: Option<String>,
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Leading directories of a glob pattern without wildcards.
+fn glob_dir(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|component| !is_glob(Path::new(component)))
+        .collect()
+}
+
+fn is_library(target: &Target) -> bool {
+    target.is_lib() || target.is_rlib() || target.is_dylib()
+}
+
+/// Features enabled by `requested`, following the `[features]` table transitively.
+///
+/// `"dep:name"` enables no feature, and `"name/feature"` enables the implicit feature of an
+/// optional dependency unless written `"name?/feature"`.
+fn feature_closure(
+    table: &BTreeMap<String, Vec<String>>,
+    requested: Vec<String>,
+) -> BTreeSet<String> {
+    let mut enabled = BTreeSet::new();
+    let mut stack = requested;
+    while let Some(feature) = stack.pop() {
+        let Some(values) = table.get(&feature) else {
+            continue;
+        };
+        if !enabled.insert(feature) {
+            continue;
         }
+        for value in values {
+            let next = match value.split_once('/') {
+                Some((dependency, _)) if !dependency.ends_with('?') => dependency,
+                Some(_) => continue,
+                None if value.starts_with("dep:") => continue,
+                None => value,
+            };
+            stack.push(next.to_string());
+        }
+    }
+    enabled
+}
 
-        Ok(map)
+impl CfgTarget {
+    /// Options set for the target, `file` is relative to `dir`.
+    fn load(&self, dir: &Path) -> anyhow::Result<Vec<syn::Meta>> {
+        let (cfg, origin) = if let Some(file) = &self.file {
+            if self.target.is_some() || self.toolchain.is_some() {
+                anyhow::bail!("`cfg_target.file` cannot be used with `target` or `toolchain`");
+            }
+            let file = dir.join(file);
+            (
+                std::fs::read_to_string(&file).map_err(|err| FileNotFound(file.clone(), err))?,
+                file.display().to_string(),
+            )
+        } else {
+            let mut command = Command::new("rustc");
+            if let Some(toolchain) = &self.toolchain {
+                command.arg(format!("+{}", toolchain));
+            }
+            command.args(["--print", "cfg"]);
+            if let Some(target) = &self.target {
+                command.args(["--target", target]);
+            }
+            let output = command
+                .output()
+                .map_err(|err| anyhow::anyhow!("failed to run `rustc --print cfg`: {}", err))?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "`rustc --print cfg` failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            (
+                String::from_utf8(output.stdout)?,
+                "`rustc --print cfg`".to_string(),
+            )
+        };
+        CfgSet::parse_target(&cfg)
+            .map_err(|err| anyhow::anyhow!("invalid cfg in {}: {}", origin, err))
     }
 }
 
 impl GitHubSource {
-    fn prepare(&self) -> anyhow::Result<TempDir> {
+    /// Check out the repository into a temporary directory and return it with the commit hash.
+    fn prepare(&self) -> anyhow::Result<(TempDir, String)> {
         let dir = tempdir()?;
 
         let mut builder = RepoBuilder::new();
@@ -200,17 +838,261 @@ impl GitHubSource {
         }
         let repo = builder.clone(&self.url, dir.path())?;
 
-        match self.dependency.as_ref() {
+        let object = match self.dependency.as_ref() {
             Some(GitDependency::Tag(tag)) => {
                 let object = repo.revparse_single(&format!("refs/tags/{}", tag))?;
                 repo.checkout_tree(&object, None)?;
+                object
             }
             Some(GitDependency::Rev(rev)) => {
                 let object = repo.revparse_single(rev)?;
                 repo.checkout_tree(&object, None)?;
+                object
             }
-            _ => {}
+            _ => repo.head()?.peel(git2::ObjectType::Commit)?,
         };
-        Ok(dir)
+        let commit = object.peel_to_commit()?.id().to_string();
+        Ok((dir, commit))
+    }
+}
+
+#[cfg(test)]
+fn test_sources(config: &str) -> Sources {
+    toml::from_str(config).unwrap()
+}
+
+#[test]
+fn test_provenance() {
+    let dir = tempfile::tempdir().unwrap();
+    let lib = dir.path().join("lib.rs");
+    std::fs::write(&lib, "mod sub;\n#[codesnip::entry]\npub fn a() {}\n").unwrap();
+    std::fs::write(
+        dir.path().join("sub.rs"),
+        "\n#[codesnip::entry(\"a\")]\npub fn b() {\n}\n",
+    )
+    .unwrap();
+    let sources = test_sources(&format!(
+        "[[sources]]\npath = {:?}\n",
+        lib.to_str().unwrap()
+    ));
+    let map = sources.snippet_map().unwrap();
+    let provenance = &map.map["a"].provenance;
+    assert_eq!(provenance.len(), 2);
+    assert_eq!(provenance[0].path, dir.path().join("sub.rs"));
+    assert_eq!(provenance[0].lines, (2, 4));
+    assert_eq!(provenance[1].path, lib);
+    assert_eq!(provenance[1].lines, (2, 3));
+    assert_eq!(provenance[1].source.as_deref(), lib.to_str());
+    assert_eq!(provenance[1].commit, None);
+}
+
+#[test]
+fn test_feature_closure() {
+    let table: BTreeMap<String, Vec<String>> = [
+        ("default", &["a"][..]),
+        ("a", &["b", "dep:x", "y/f", "z?/g"]),
+        ("b", &[]),
+        ("c", &["b"]),
+        ("x", &[]),
+        ("y", &[]),
+        ("z", &[]),
+    ]
+    .into_iter()
+    .map(|(name, values)| {
+        let values = values.iter().map(|value| value.to_string()).collect();
+        (name.to_string(), values)
+    })
+    .collect();
+    let closure = |requested: &[&str]| {
+        let requested = requested.iter().map(|name| name.to_string()).collect();
+        feature_closure(&table, requested)
+            .into_iter()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(closure(&["default"]), ["a", "b", "default", "y"]);
+    assert_eq!(closure(&["c", "unknown"]), ["b", "c"]);
+    assert_eq!(closure(&[]), Vec::<String>::new());
+}
+
+#[test]
+fn test_path_roots() {
+    let dir = tempfile::tempdir().unwrap();
+    let snippets = dir.path().join("snippets");
+    std::fs::create_dir_all(snippets.join("nested")).unwrap();
+    for file in ["a.rs", "b.rs", "skip.rs", "notes.txt", "nested/c.rs"] {
+        std::fs::write(snippets.join(file), "").unwrap();
+    }
+    let source = |config: &str| test_sources(config).sources.remove(0);
+    let roots = |source: &Source, path: &str| {
+        source.path_roots(Path::new(path), dir.path()).map(|roots| {
+            roots
+                .into_iter()
+                .map(|root| (root.path, root.prefix))
+                .collect::<Vec<_>>()
+        })
+    };
+    let root = |path: &str, prefix: Option<&str>| (PathBuf::from(path), prefix.map(String::from));
+
+    let glob = source(concat!(
+        "[[sources]]\npath = \"snippets/*.rs\"\nexclude = [\"*/skip.rs\"]\n",
+        "prefix = \"s\"\nstem_prefix = true\n",
+    ));
+    assert_eq!(
+        roots(&glob, "snippets/*.rs").unwrap(),
+        [
+            root("snippets/a.rs", Some("s_a")),
+            root("snippets/b.rs", Some("s_b")),
+        ]
+    );
+    assert_eq!(
+        roots(&glob, "snippets/**/*.rs").unwrap(),
+        [
+            root("snippets/a.rs", Some("s_a")),
+            root("snippets/b.rs", Some("s_b")),
+            root("snippets/nested/c.rs", Some("s_c")),
+        ]
+    );
+    let error = roots(&glob, "snippets/*.toml").unwrap_err();
+    assert_eq!(error.to_string(), "no file matches `snippets/*.toml`");
+
+    let file = source("[[sources]]\npath = \"snippets/a.rs\"\nprefix = \"s\"\n");
+    assert_eq!(
+        roots(&file, "snippets/a.rs").unwrap(),
+        [root("snippets/a.rs", Some("s"))]
+    );
+    let file = source("[[sources]]\npath = \"snippets/a.rs\"\nstem_prefix = true\n");
+    assert_eq!(
+        roots(&file, "snippets/a.rs").unwrap(),
+        [root("snippets/a.rs", Some("a"))]
+    );
+}
+
+#[test]
+fn test_package_roots() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().canonicalize().unwrap();
+    let write = |path: &str, contents: &str| {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    };
+    write(
+        "Cargo.toml",
+        "[workspace]\nmembers = [\"foo\", \"bar\", \"app\"]\nresolver = \"2\"\n",
+    );
+    for (package, target) in [("foo", "lib"), ("bar-crate", "lib"), ("app", "main")] {
+        let member = package.trim_end_matches("-crate");
+        write(
+            &format!("{}/Cargo.toml", member),
+            &format!(
+                "[package]\nname = {:?}\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+                package
+            ),
+        );
+        write(&format!("{}/src/{}.rs", member, target), "");
+    }
+    let source = |config: &str| test_sources(config).sources.remove(0);
+    let roots = |source: &Source| {
+        source.package_roots(&dir).map(|(roots, manifests)| {
+            let mut roots = roots
+                .into_iter()
+                .map(|root| (root.path, root.prefix))
+                .collect::<Vec<_>>();
+            roots.sort();
+            (roots, manifests.into_iter().collect::<BTreeSet<_>>())
+        })
+    };
+    let root = |path: &str, prefix: Option<&str>| (PathBuf::from(path), prefix.map(String::from));
+    let manifests = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<BTreeSet<_>>();
+
+    let workspace = source("[[sources]]\nworkspace = true\nprefix = \"ws\"\n");
+    assert_eq!(
+        roots(&workspace).unwrap(),
+        (
+            vec![
+                root("bar/src/lib.rs", Some("ws_bar_crate")),
+                root("foo/src/lib.rs", Some("ws_foo")),
+            ],
+            manifests(&["Cargo.toml", "bar/Cargo.toml", "foo/Cargo.toml"]),
+        )
+    );
+    let workspace = source("[[sources]]\nworkspace = true\nmanifest_path = \"app/Cargo.toml\"\n");
+    assert_eq!(
+        roots(&workspace).unwrap().0,
+        [
+            root("bar/src/lib.rs", Some("bar_crate")),
+            root("foo/src/lib.rs", Some("foo")),
+        ]
+    );
+
+    let package = source("[[sources]]\npackage = \"bar-crate\"\nprefix = \"ws\"\n");
+    assert_eq!(
+        roots(&package).unwrap(),
+        (
+            vec![root("bar/src/lib.rs", Some("ws"))],
+            manifests(&["Cargo.toml", "bar/Cargo.toml"]),
+        )
+    );
+    let error = roots(&source("[[sources]]\npackage = \"app\"\n")).unwrap_err();
+    assert_eq!(error.to_string(), "package `app` has no library target");
+    let error = roots(&source("[[sources]]\npackage = \"baz\"\n")).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!("package `baz` not found in workspace `{}`", dir.display())
+    );
+}
+
+#[test]
+fn test_discover() {
+    let dir = tempfile::tempdir().unwrap();
+    let parent = dir.path().canonicalize().unwrap();
+    let child = parent.join("child");
+    let deeper = child.join("deeper");
+    std::fs::create_dir_all(&deeper).unwrap();
+    let sources = "[[sources]]\npath = \"lib.rs\"\n";
+    std::fs::write(parent.join("codesnip.toml"), sources).unwrap();
+    assert_eq!(
+        Sources::discover(&deeper).unwrap(),
+        parent.join("codesnip.toml")
+    );
+
+    // A manifest without the config is passed over.
+    std::fs::write(deeper.join("Cargo.toml"), "[package]\nname = \"deeper\"\n").unwrap();
+    std::fs::write(
+        child.join("Cargo.toml"),
+        "[workspace]\n[[workspace.metadata.codesnip.sources]]\npath = \"lib.rs\"\n",
+    )
+    .unwrap();
+    assert_eq!(
+        Sources::discover(&deeper).unwrap(),
+        child.join("Cargo.toml")
+    );
+    std::fs::write(child.join(".codesnip.toml"), sources).unwrap();
+    assert_eq!(
+        Sources::discover(&deeper).unwrap(),
+        child.join(".codesnip.toml")
+    );
+
+    // Only a discovered config resolves relative paths from its directory.
+    let manifest = child.join("Cargo.toml");
+    assert_eq!(Sources::load_discovered(&manifest).unwrap().dir, child);
+    assert_eq!(Sources::load(&manifest).unwrap().dir, PathBuf::new());
+}
+
+#[test]
+fn test_from_manifest() {
+    for key in ["package", "workspace"] {
+        let manifest = format!(
+            "[{}]\n[[{}.metadata.codesnip.sources]]\npath = \"src/lib.rs\"\nprefix = \"p\"\n",
+            key, key
+        );
+        let sources = Sources::from_manifest(&manifest).unwrap().unwrap();
+        assert_eq!(sources.sources.len(), 1);
+        assert_eq!(sources.sources[0].path, Some(PathBuf::from("src/lib.rs")));
+        assert_eq!(sources.sources[0].prefix.as_deref(), Some("p"));
     }
+    let manifest = "[package]\nname = \"a\"\n[package.metadata.other]\nkey = 1\n";
+    assert!(Sources::from_manifest(manifest).unwrap().is_none());
+    let manifest = "[package.metadata.codesnip]\nsources = 1\n";
+    assert!(Sources::from_manifest(manifest).is_err());
 }
@@ -1,3 +1,4 @@
+use crate::mapping::deprecation_warnings;
 use anyhow::Context as _;
 use cargo_metadata::diagnostic::{Diagnostic, DiagnosticLevel};
 use codesnip_core::SnippetMap;
@@ -45,20 +46,25 @@ pub fn execute(
         }
     }
 
+    for unresolved in map.unresolved_includes() {
+        ok.store(false, std::sync::atomic::Ordering::Relaxed);
+        pb_println!(
+            "{}: Invalid include `{}` in {}.",
+            style("warning").yellow(),
+            unresolved.include,
+            unresolved.chain.join(" -> ")
+        );
+    }
+
+    for name in map.map.keys() {
+        for warning in deprecation_warnings(&map, &[name], &Default::default()) {
+            pb_println!("{}: {}.", style("warning").yellow(), warning);
+        }
+    }
+
     map.map.par_iter().for_each(|(name, link)| {
         pb.set_message(name.to_owned());
-        for include in link.includes.iter() {
-            if !map.map.contains_key(include) {
-                ok.store(false, std::sync::atomic::Ordering::Relaxed);
-                pb_println!(
-                    "{}: Invalid include `{}` in {}.",
-                    style("warning").yellow(),
-                    include,
-                    name
-                );
-            }
-        }
-        let contents = map.bundle(name, link, Default::default(), false);
+        let contents = map.bundle(name, link, Default::default(), false, Default::default());
         match check(name, &contents, toolchain, edition, target) {
             Ok((success, messages)) => {
                 if !success {
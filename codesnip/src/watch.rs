@@ -0,0 +1,171 @@
+use crate::{Config, cache::hash_bytes, invalidate_spans, source::Sources};
+use anyhow::Context as _;
+use notify_debouncer_mini::{DebounceEventResult, new_debouncer, notify::RecursiveMode};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Write as _, stdout},
+    path::{Path, PathBuf},
+    sync::mpsc::channel,
+    time::Duration,
+};
+
+/// Time to wait for further changes before rebuilding.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Run the subcommand, then again on every change of the source config or its local files.
+///
+/// Errors while rebuilding are reported and the files are watched again.
+pub fn execute(config: &Config) -> anyhow::Result<()> {
+    let source_config = config
+        .source_config
+        .as_ref()
+        .context("`--watch` requires `--source-config`")?;
+    let (mut previous, caches) = config.load_caches()?;
+    let (tx, rx) = channel();
+    let mut debouncer = new_debouncer(DEBOUNCE, tx)?;
+    // Hashes of watched files when last built, reading a file also emits events.
+    let mut files = BTreeMap::from([(absolute(source_config)?, None)]);
+    let mut dirs = BTreeSet::new();
+    let mut recursive_dirs: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut sources = None;
+    loop {
+        for (file, hash) in files.iter_mut() {
+            *hash = hash_file(file);
+        }
+        invalidate_spans();
+        match config.build(&previous, &caches) {
+            Ok(cache) => {
+                previous = cache.incremental.clone();
+                if let Err(err) = config.cmd.execute(cache) {
+                    eprintln!("error: {}", err);
+                }
+            }
+            Err(err) => eprintln!("error: {}", err),
+        }
+        stdout().flush()?;
+        // Files of the last successful build are kept, a file may fail to parse while edited.
+        if let Ok(Some(loaded)) = config.load_sources() {
+            let (roots, root_dirs) = loaded.local_roots().unwrap_or_default();
+            for file in loaded.local_files(&previous).into_iter().chain(roots) {
+                let file = absolute(&file)?;
+                files.entry(file).or_insert_with_key(|file| hash_file(file));
+            }
+            // Directories of roots are watched recursively for new roots and modules.
+            for dir in root_dirs {
+                let dir = absolute(&dir)?;
+                if dir.exists()
+                    && !recursive_dirs
+                        .iter()
+                        .any(|watched| dir.starts_with(watched))
+                {
+                    debouncer.watcher().watch(&dir, RecursiveMode::Recursive)?;
+                    recursive_dirs.insert(dir);
+                }
+            }
+            sources = Some(loaded);
+        }
+        // Directories are watched instead of files, editors often replace a file on save.
+        for dir in files.keys().filter_map(|file| file.parent()) {
+            if !dirs.contains(dir)
+                && !recursive_dirs
+                    .iter()
+                    .any(|watched| dir.starts_with(watched))
+                && dir.exists()
+            {
+                debouncer
+                    .watcher()
+                    .watch(dir, RecursiveMode::NonRecursive)?;
+                dirs.insert(dir.to_path_buf());
+            }
+        }
+        eprintln!("watching {} files for changes...", files.len());
+
+        loop {
+            let events: DebounceEventResult = rx.recv()?;
+            let events = events.map_err(|err| anyhow::anyhow!("failed to watch files: {}", err))?;
+            let changed = events
+                .iter()
+                .any(|event| is_changed(&files, sources.as_ref(), &event.path));
+            if changed {
+                break;
+            }
+        }
+    }
+}
+
+/// Whether `path` has changed since the last build, or is a new root file of `sources`.
+fn is_changed(
+    files: &BTreeMap<PathBuf, Option<String>>,
+    sources: Option<&Sources>,
+    path: &Path,
+) -> bool {
+    match files.get(path) {
+        Some(hash) => *hash != hash_file(path),
+        None => {
+            path.extension().is_some_and(|ext| ext == "rs")
+                && sources
+                    .and_then(|sources| sources.local_roots().ok())
+                    .is_some_and(|(roots, _)| {
+                        roots
+                            .iter()
+                            .any(|root| absolute(root).is_ok_and(|root| root == path))
+                    })
+        }
+    }
+}
+
+/// `None` if the file cannot be read.
+fn hash_file(path: &Path) -> Option<String> {
+    std::fs::read(path)
+        .ok()
+        .map(|contents| hash_bytes(&contents))
+}
+
+/// Path as reported by the watcher, `..` is resolved unless the file is missing.
+fn absolute(path: &Path) -> anyhow::Result<PathBuf> {
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .map_err(|err| anyhow::anyhow!("invalid path `{}`: {}", path.display(), err))
+}
+
+#[test]
+fn test_is_changed() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = absolute(temp.path()).unwrap();
+    let snippets = dir.join("snippets");
+    std::fs::create_dir_all(snippets.join("nested")).unwrap();
+    let a = snippets.join("a.rs");
+    std::fs::write(&a, "pub fn a() {}\n").unwrap();
+    let config = dir.join("codesnip.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "[[sources]]\npath = {:?}\n",
+            snippets.join("*.rs").to_str().unwrap()
+        ),
+    )
+    .unwrap();
+    let sources = Sources::load(&config).unwrap();
+    let (roots, dirs) = sources.local_roots().unwrap();
+    assert_eq!(roots, BTreeSet::from([a.clone()]));
+    assert_eq!(dirs, BTreeSet::from([snippets.clone()]));
+
+    let files = BTreeMap::from([(a.clone(), hash_file(&a))]);
+    let is_changed = |path: &Path| is_changed(&files, Some(&sources), path);
+    assert!(!is_changed(&a));
+    std::fs::write(&a, "pub fn a() { }\n").unwrap();
+    assert!(is_changed(&a));
+    for (file, matched) in [
+        (snippets.join("b.rs"), true),
+        (snippets.join("notes.txt"), false),
+        (snippets.join("nested").join("c.rs"), false),
+    ] {
+        std::fs::write(&file, "").unwrap();
+        assert_eq!(is_changed(&file), matched, "{}", file.display());
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::BTreeSet;
+use syn::{Expr, ExprLit, Lit, Meta, Token, punctuated::Punctuated};
+
+/// Names of options which are set only by the target or the compiler, unset unless listed by
+/// `rustc --print cfg`.
+const WELL_KNOWN_NAMES: [&str; 11] = [
+    "unix",
+    "windows",
+    "test",
+    "doc",
+    "doctest",
+    "miri",
+    "debug_assertions",
+    "overflow_checks",
+    "proc_macro",
+    "target_thread_local",
+    "ub_checks",
+];
+
+/// Configuration to evaluate `cfg` predicates against.
+#[derive(Debug, Default, Clone)]
+pub struct CfgSet {
+    enable: Vec<Meta>,
+    disable: Vec<Meta>,
+    /// Options set for the target, `None` if no target is given.
+    target: Option<Vec<Meta>>,
+    /// Enabled cargo features, `None` if unknown.
+    features: Option<BTreeSet<String>>,
+}
+
+impl CfgSet {
+    /// Options in `enable` are set and ones in `disable` are not, the rest are unknown.
+    pub fn new(enable: &[Meta], disable: &[Meta]) -> Self {
+        Self {
+            enable: enable.to_vec(),
+            disable: disable.to_vec(),
+            target: None,
+            features: None,
+        }
+    }
+    /// Decide options of the target by `target`, which `enable` and `disable` override.
+    pub fn target(self, target: Vec<Meta>) -> Self {
+        Self {
+            target: Some(target),
+            ..self
+        }
+    }
+    /// Decide `feature = "..."` by `features`, which `enable` and `disable` override.
+    pub fn features(self, features: BTreeSet<String>) -> Self {
+        Self {
+            features: Some(features),
+            ..self
+        }
+    }
+    /// Parse options printed by `rustc --print cfg`, one per line.
+    pub fn parse_target(s: &str) -> syn::Result<Vec<Meta>> {
+        s.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(syn::parse_str)
+            .collect()
+    }
+    /// Evaluate `pred`, `None` if it depends on unknown options.
+    pub fn evaluate(&self, pred: &Meta) -> Option<bool> {
+        if let Some(id) = pred.path().get_ident() {
+            let args = || match pred {
+                Meta::List(list) => list
+                    .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                    .ok(),
+                _ => None,
+            };
+            match id.to_string().as_str() {
+                "all" => {
+                    let mut result = Some(true);
+                    for pred in args()?.iter() {
+                        match self.evaluate(pred) {
+                            Some(true) => {}
+                            Some(false) => return Some(false),
+                            None => result = None,
+                        }
+                    }
+                    return result;
+                }
+                "any" => {
+                    let mut result = Some(false);
+                    for pred in args()?.iter() {
+                        match self.evaluate(pred) {
+                            Some(true) => return Some(true),
+                            Some(false) => {}
+                            None => result = None,
+                        }
+                    }
+                    return result;
+                }
+                "not" => {
+                    if let Meta::List(list) = pred {
+                        let pred = list.parse_args().ok()?;
+                        return self.evaluate(&pred).map(|pred| !pred);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if self.disable.iter().any(|spec| spec == pred) {
+            Some(false)
+        } else if self.enable.iter().any(|spec| spec == pred) {
+            Some(true)
+        } else {
+            self.evaluate_feature(pred)
+                .or_else(|| self.evaluate_target(pred))
+        }
+    }
+
+    fn evaluate_feature(&self, pred: &Meta) -> Option<bool> {
+        let features = self.features.as_ref()?;
+        match pred {
+            Meta::NameValue(meta) if meta.path.is_ident("feature") => match &meta.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(litstr),
+                    ..
+                }) => Some(features.contains(&litstr.value())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn evaluate_target(&self, pred: &Meta) -> Option<bool> {
+        let target = self.target.as_ref()?;
+        if target.iter().any(|spec| spec == pred) {
+            return Some(true);
+        }
+        let name = pred.path().get_ident()?.to_string();
+        let is_closed = match pred {
+            Meta::Path(_) => WELL_KNOWN_NAMES.contains(&name.as_str()),
+            Meta::NameValue(_) => {
+                name.starts_with("target_")
+                    || target.iter().any(|spec| {
+                        matches!(spec, Meta::NameValue(_)) && spec.path().is_ident(&name)
+                    })
+            }
+            Meta::List(_) => false,
+        };
+        if is_closed { Some(false) } else { None }
+    }
+}
+
+#[test]
+fn test_cfg_condition_enable_disable() {
+    let enable = vec![syn::parse_str::<Meta>("feature = \"foo\"").unwrap()];
+    let disable = vec![syn::parse_str::<Meta>("feature = \"bar\"").unwrap()];
+    let cfg = CfgSet::new(&enable, &disable);
+    let evaluate = |pred: &str| cfg.evaluate(&syn::parse_str(pred).unwrap());
+
+    assert_eq!(evaluate("feature = \"foo\""), Some(true));
+    assert_eq!(evaluate("feature = \"bar\""), Some(false));
+    assert_eq!(evaluate("target_arch = \"x86_64\""), None);
+    assert_eq!(
+        evaluate("any(feature = \"foo\", target_arch = \"x86_64\")"),
+        Some(true)
+    );
+    assert_eq!(
+        evaluate("any(feature = \"bar\", target_arch = \"x86_64\")"),
+        None
+    );
+    assert_eq!(
+        evaluate("all(feature = \"bar\", target_arch = \"x86_64\")"),
+        Some(false)
+    );
+    assert_eq!(
+        evaluate("not(all(feature = \"foo\", target_arch = \"x86_64\"))"),
+        None
+    );
+}
+
+#[test]
+fn test_cfg_target() {
+    let target = CfgSet::parse_target(
+        "debug_assertions\npanic=\"unwind\"\ntarget_arch=\"x86_64\"\ntarget_os=\"linux\"\ntarget_pointer_width=\"64\"\nunix\n",
+    )
+    .unwrap();
+    let disable = vec![syn::parse_str::<Meta>("debug_assertions").unwrap()];
+    let cfg = CfgSet::new(&[], &disable).target(target);
+    let evaluate = |pred: &str| cfg.evaluate(&syn::parse_str(pred).unwrap());
+
+    assert_eq!(evaluate("target_pointer_width = \"64\""), Some(true));
+    assert_eq!(evaluate("target_pointer_width = \"32\""), Some(false));
+    assert_eq!(evaluate("target_has_atomic = \"128\""), Some(false));
+    assert_eq!(evaluate("panic = \"abort\""), Some(false));
+    assert_eq!(evaluate("unix"), Some(true));
+    assert_eq!(evaluate("windows"), Some(false));
+    assert_eq!(evaluate("debug_assertions"), Some(false));
+    assert_eq!(evaluate("feature = \"foo\""), None);
+    assert_eq!(evaluate("nightly"), None);
+    assert_eq!(evaluate("any(windows, feature = \"foo\")"), None);
+    assert_eq!(evaluate("all(unix, not(windows))"), Some(true));
+    assert_eq!(evaluate("any(unix, feature = \"foo\")"), Some(true));
+}
+
+#[test]
+fn test_cfg_features() {
+    let disable = vec![syn::parse_str::<Meta>("feature = \"b\"").unwrap()];
+    let cfg = CfgSet::new(&[], &disable).features(["a", "b"].map(String::from).into());
+    let evaluate = |pred: &str| cfg.evaluate(&syn::parse_str(pred).unwrap());
+
+    assert_eq!(evaluate("feature = \"a\""), Some(true));
+    assert_eq!(evaluate("feature = \"b\""), Some(false));
+    assert_eq!(evaluate("feature = \"c\""), Some(false));
+    assert_eq!(evaluate("any(feature = \"c\", unix)"), None);
+    assert_eq!(
+        evaluate("all(feature = \"a\", not(feature = \"c\"))"),
+        Some(true)
+    );
+}
@@ -1,7 +1,7 @@
-use crate::ext::ItemExt as _;
+use crate::ext::{AttributeExt as _, ItemExt as _, PathExt as _};
 use quote::ToTokens;
 use syn::{
-    Error, Ident, Item, LitStr, Token,
+    Attribute, Error, Expr, ExprLit, Ident, Item, Lit, LitStr, Meta, MetaNameValue, Token,
     ext::IdentExt,
     parenthesized,
     parse::{Parse, ParseStream},
@@ -14,6 +14,12 @@ pub struct Entry {
     pub name: String,
     pub include: Vec<String>,
     pub inline: bool,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub category: Option<String>,
+    pub aliases: Vec<String>,
+    /// Deprecation note, empty if deprecated without one.
+    pub deprecated: Option<String>,
 }
 
 #[derive(Eq, PartialEq, Clone, Debug, Hash)]
@@ -27,6 +33,11 @@ pub enum EntryArg {
     Include(EntryArgInclude),
     Inline(EntryArgInline),
     NoInline(EntryArgNoInline),
+    Description(EntryArgDescription),
+    Tags(EntryArgTags),
+    Category(EntryArgCategory),
+    Alias(EntryArgAlias),
+    Deprecated(EntryArgDeprecated),
 }
 
 #[derive(Eq, PartialEq, Clone, Debug, Hash)]
@@ -52,17 +63,66 @@ pub struct EntryArgNoInline {
     pub token: Ident,
 }
 
+#[derive(Eq, PartialEq, Clone, Debug, Hash)]
+pub struct EntryArgDescription {
+    pub description_token: Ident,
+    pub eq_token: token::Eq,
+    pub description: LitStr,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Hash)]
+pub struct EntryArgTags {
+    pub tags_token: Ident,
+    pub paren_token: Paren,
+    pub tags: Punctuated<NoWhitespaceLitStr, Token![,]>,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Hash)]
+pub struct EntryArgCategory {
+    pub category_token: Ident,
+    pub eq_token: token::Eq,
+    pub category: LitStr,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Hash)]
+pub struct EntryArgAlias {
+    pub alias_token: Ident,
+    pub paren_token: Paren,
+    pub aliases: Punctuated<NoWhitespaceLitStr, Token![,]>,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Hash)]
+pub struct EntryArgDeprecated {
+    pub deprecated_token: Ident,
+    pub note: Option<(token::Eq, LitStr)>,
+}
+
 #[derive(Eq, PartialEq, Clone, Debug, Hash)]
 pub struct NoWhitespaceLitStr {
     pub litstr: LitStr,
 }
 
+const EXPECTED_ARGS: &str = "expected `name` | `include` | `inline` | `no_inline` | `description` | `tags` | `category` | `alias` | `deprecated`";
+
+/// Entries specified by `#[codesnip::entry]` attributes of `item`.
+pub(crate) fn parse_entries(item: &Item) -> Vec<Entry> {
+    item.get_attributes()
+        .into_iter()
+        .flatten()
+        .filter(|attr| attr.path().is_codesnip_entry())
+        .filter_map(|attr| attr.parse_args_empty_with(EntryArgs::parse).ok())
+        .filter_map(|args| args.try_to_entry(item).ok())
+        .collect()
+}
+
 impl EntryArgs {
     pub fn try_to_entry(&self, item: &Item) -> syn::Result<Entry> {
         let default_name = item.get_default_name();
         let mut entry = Entry::default();
         let mut name = None;
         let mut inline = None;
+        let mut description = None;
+        let mut category = None;
         for arg in self.args.iter() {
             match arg {
                 EntryArg::Name(arg) => {
@@ -108,11 +168,44 @@ impl EntryArgs {
                     }
                     inline = Some(false);
                 }
+                EntryArg::Description(arg) => {
+                    if description.is_some() {
+                        return Err(Error::new_spanned(arg, "duplicate `description` specified"));
+                    }
+                    description = Some(arg.description.value());
+                }
+                EntryArg::Tags(arg) => {
+                    entry.tags.extend(arg.tags.iter().map(|lit| lit.value()));
+                }
+                EntryArg::Category(arg) => {
+                    if category.is_some() {
+                        return Err(Error::new_spanned(arg, "duplicate `category` specified"));
+                    }
+                    category = Some(arg.category.value());
+                }
+                EntryArg::Deprecated(arg) => {
+                    if entry.deprecated.is_some() {
+                        return Err(Error::new_spanned(arg, "duplicate `deprecated` specified"));
+                    }
+                    entry.deprecated = Some(
+                        arg.note
+                            .as_ref()
+                            .map(|(_, note)| note.value())
+                            .unwrap_or_default(),
+                    );
+                }
+                EntryArg::Alias(arg) => {
+                    entry
+                        .aliases
+                        .extend(arg.aliases.iter().map(|lit| lit.value()));
+                }
             }
         }
         if let Some(inline) = inline {
             entry.inline = inline;
         }
+        entry.description = description.or_else(|| item.get_attributes().and_then(doc_summary));
+        entry.category = category;
         if name.is_none() {
             name = default_name;
         }
@@ -147,10 +240,19 @@ impl Parse for EntryArg {
                 "no_inline" => {
                     EntryArgNoInline::parse_after_token(token, input).map(Self::NoInline)
                 }
-                _ => Err(input.error("expected `name` | `include` | `inline` | `no_inline`")),
+                "description" => {
+                    EntryArgDescription::parse_after_token(token, input).map(Self::Description)
+                }
+                "tags" => EntryArgTags::parse_after_token(token, input).map(Self::Tags),
+                "category" => EntryArgCategory::parse_after_token(token, input).map(Self::Category),
+                "alias" => EntryArgAlias::parse_after_token(token, input).map(Self::Alias),
+                "deprecated" => {
+                    EntryArgDeprecated::parse_after_token(token, input).map(Self::Deprecated)
+                }
+                _ => Err(input.error(EXPECTED_ARGS)),
             }
         } else {
-            Err(input.error("expected `name` | `include` | `inline` | `no_inline`"))
+            Err(input.error(EXPECTED_ARGS))
         }
     }
 }
@@ -198,6 +300,64 @@ impl EntryArgInclude {
     }
 }
 
+impl EntryArgDescription {
+    fn parse_after_token(description_token: Ident, input: ParseStream) -> syn::Result<Self> {
+        Ok(Self {
+            description_token,
+            eq_token: input.parse()?,
+            description: input.parse()?,
+        })
+    }
+}
+
+#[allow(clippy::mixed_read_write_in_expression)]
+impl EntryArgTags {
+    fn parse_after_token(tags_token: Ident, input: ParseStream) -> syn::Result<Self> {
+        let content;
+        Ok(Self {
+            tags_token,
+            paren_token: parenthesized!(content in input),
+            tags: content.call(Punctuated::parse_separated_nonempty)?,
+        })
+    }
+}
+
+#[allow(clippy::mixed_read_write_in_expression)]
+impl EntryArgAlias {
+    fn parse_after_token(alias_token: Ident, input: ParseStream) -> syn::Result<Self> {
+        let content;
+        Ok(Self {
+            alias_token,
+            paren_token: parenthesized!(content in input),
+            aliases: content.call(Punctuated::parse_separated_nonempty)?,
+        })
+    }
+}
+
+impl EntryArgDeprecated {
+    fn parse_after_token(deprecated_token: Ident, input: ParseStream) -> syn::Result<Self> {
+        let note = if input.peek(Token![=]) {
+            Some((input.parse()?, input.parse()?))
+        } else {
+            None
+        };
+        Ok(Self {
+            deprecated_token,
+            note,
+        })
+    }
+}
+
+impl EntryArgCategory {
+    fn parse_after_token(category_token: Ident, input: ParseStream) -> syn::Result<Self> {
+        Ok(Self {
+            category_token,
+            eq_token: input.parse()?,
+            category: input.parse()?,
+        })
+    }
+}
+
 #[allow(clippy::unnecessary_wraps)]
 impl EntryArgInline {
     fn parse_after_token(token: Ident, _input: ParseStream) -> syn::Result<Self> {
@@ -231,6 +391,11 @@ impl ToTokens for EntryArg {
             EntryArg::Include(arg) => arg.to_tokens(tokens),
             EntryArg::Inline(arg) => arg.to_tokens(tokens),
             EntryArg::NoInline(arg) => arg.to_tokens(tokens),
+            EntryArg::Description(arg) => arg.to_tokens(tokens),
+            EntryArg::Tags(arg) => arg.to_tokens(tokens),
+            EntryArg::Category(arg) => arg.to_tokens(tokens),
+            EntryArg::Alias(arg) => arg.to_tokens(tokens),
+            EntryArg::Deprecated(arg) => arg.to_tokens(tokens),
         }
     }
 }
@@ -253,6 +418,48 @@ impl ToTokens for EntryArgInclude {
     }
 }
 
+impl ToTokens for EntryArgDescription {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        self.description_token.to_tokens(tokens);
+        self.eq_token.to_tokens(tokens);
+        self.description.to_tokens(tokens);
+    }
+}
+
+impl ToTokens for EntryArgTags {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        self.tags_token.to_tokens(tokens);
+        self.paren_token
+            .surround(tokens, |tokens| self.tags.to_tokens(tokens));
+    }
+}
+
+impl ToTokens for EntryArgCategory {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        self.category_token.to_tokens(tokens);
+        self.eq_token.to_tokens(tokens);
+        self.category.to_tokens(tokens);
+    }
+}
+
+impl ToTokens for EntryArgAlias {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        self.alias_token.to_tokens(tokens);
+        self.paren_token
+            .surround(tokens, |tokens| self.aliases.to_tokens(tokens));
+    }
+}
+
+impl ToTokens for EntryArgDeprecated {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        self.deprecated_token.to_tokens(tokens);
+        if let Some((eq_token, note)) = &self.note {
+            eq_token.to_tokens(tokens);
+            note.to_tokens(tokens);
+        }
+    }
+}
+
 impl ToTokens for EntryArgInline {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         self.token.to_tokens(tokens)
@@ -270,3 +477,107 @@ impl ToTokens for NoWhitespaceLitStr {
         self.litstr.to_tokens(tokens)
     }
 }
+
+/// Trimmed lines of the doc comment.
+fn doc_lines(attrs: &[Attribute]) -> impl Iterator<Item = String> + '_ {
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(MetaNameValue {
+                value:
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Str(litstr),
+                        ..
+                    }),
+                ..
+            }) => Some(litstr.value()),
+            _ => None,
+        })
+        .flat_map(|doc| {
+            doc.split('\n')
+                .map(|line| line.trim().to_string())
+                .collect::<Vec<_>>()
+        })
+}
+
+fn join_lines(lines: impl Iterator<Item = String>) -> Option<String> {
+    let lines: Vec<String> = lines.collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join(" "))
+    }
+}
+
+/// Whole doc comment, lines joined by spaces.
+pub fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    join_lines(doc_lines(attrs).filter(|line| !line.is_empty()))
+}
+
+/// First paragraph of the doc comment.
+pub fn doc_summary(attrs: &[Attribute]) -> Option<String> {
+    join_lines(
+        doc_lines(attrs)
+            .skip_while(|line| line.is_empty())
+            .take_while(|line| !line.is_empty()),
+    )
+}
+
+#[test]
+fn test_entry_metadata() {
+    let item: Item = syn::parse_quote! {
+        /// Greatest common divisor.
+        ///
+        /// Details.
+        #[codesnip::entry(tags("math", "number"), category = "math")]
+        pub fn gcd() {}
+    };
+    let entry = &parse_entries(&item)[0];
+    assert_eq!(
+        entry.description.as_deref(),
+        Some("Greatest common divisor.")
+    );
+    assert_eq!(entry.tags, ["math", "number"]);
+    assert_eq!(entry.category.as_deref(), Some("math"));
+
+    let item: Item = syn::parse_quote! {
+        /// Ignored.
+        #[codesnip::entry(description = "Least common multiple.")]
+        pub fn lcm() {}
+    };
+    let entry = &parse_entries(&item)[0];
+    assert_eq!(entry.description.as_deref(), Some("Least common multiple."));
+
+    let args: EntryArgs = syn::parse_quote!(category = "a", category = "b");
+    assert!(args.try_to_entry(&item).is_err());
+
+    let args: EntryArgs = syn::parse_quote!(deprecated);
+    let entry = args.try_to_entry(&item).unwrap();
+    assert_eq!(entry.deprecated.as_deref(), Some(""));
+    let args: EntryArgs = syn::parse_quote!(deprecated = "use gcd instead");
+    let entry = args.try_to_entry(&item).unwrap();
+    assert_eq!(entry.deprecated.as_deref(), Some("use gcd instead"));
+    assert_eq!(
+        args.to_token_stream().to_string(),
+        r#"deprecated = "use gcd instead""#
+    );
+}
+
+#[test]
+fn test_doc() {
+    let item: syn::ItemFn = syn::parse_str(
+        "/// Greatest\n/// common divisor.\n///\n/// Euclid.\n#[inline]\nfn gcd() {}",
+    )
+    .unwrap();
+    assert_eq!(
+        doc_comment(&item.attrs).as_deref(),
+        Some("Greatest common divisor. Euclid.")
+    );
+    assert_eq!(
+        doc_summary(&item.attrs).as_deref(),
+        Some("Greatest common divisor.")
+    );
+    assert_eq!(doc_comment(&[]), None);
+    assert_eq!(doc_summary(&[]), None);
+}
@@ -1,5 +1,6 @@
 use rust_minify::{MinifyOption, minify_opt};
 use std::{
+    fmt,
     io::Write as _,
     path::Path,
     process::{Command, Stdio},
@@ -24,6 +25,15 @@ impl FromStr for FormatOption {
     }
 }
 
+impl fmt::Display for FormatOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rustfmt => write!(f, "rustfmt"),
+            Self::Minify => write!(f, "minify"),
+        }
+    }
+}
+
 impl FormatOption {
     pub const POSSIBLE_VALUES: [&'static str; 2] = ["rustfmt", "minify"];
     pub fn format(&self, content: &str) -> Option<String> {
@@ -41,6 +51,18 @@ impl FormatOption {
     }
 }
 
+/// Minify without the attribute keeping rustfmt away, to measure the minified size.
+pub fn minify(content: &str) -> Option<String> {
+    minify_opt(
+        content,
+        &MinifyOption {
+            remove_skip: true,
+            add_rustfmt_skip: false,
+        },
+    )
+    .ok()
+}
+
 pub fn rustfmt_exits() -> bool {
     let rustfmt = Path::new(env!("CARGO_HOME")).join("bin").join("rustfmt");
     let output = Command::new(rustfmt).arg("--version").output();
@@ -78,3 +100,12 @@ fn test_format_contents() {
         Some("fn main() {}\n".to_string())
     )
 }
+
+#[test]
+fn test_minify() {
+    let content = "pub fn bar() -> u32 {\n    2\n}\n";
+    let minified = minify(content).unwrap();
+    assert!(!minified.contains("rustfmt"));
+    assert!(minified.len() < content.len());
+    assert!(FormatOption::Minify.format(content).unwrap().len() > minified.len());
+}
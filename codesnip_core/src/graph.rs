@@ -0,0 +1,120 @@
+use crate::map::SnippetMap;
+use std::collections::{BTreeMap, BTreeSet};
+
+impl SnippetMap {
+    /// Snippets included by `name` directly or transitively, except `name` itself.
+    pub fn includes_of<'s>(&'s self, name: &'s str) -> BTreeSet<&'s str> {
+        let Some(link) = self.map.get(name) else {
+            return BTreeSet::new();
+        };
+        let mut includes = self.resolve_includes(
+            &Default::default(),
+            link.includes.iter().map(|s| s.as_str()),
+        );
+        includes.remove(name);
+        includes
+    }
+
+    /// Snippets including `name` directly or transitively, except `name` itself.
+    pub fn included_by<'s>(&'s self, name: &'s str) -> BTreeSet<&'s str> {
+        let mut reverse: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (from, link) in self.map.iter() {
+            for include in link.includes.iter() {
+                reverse.entry(include).or_default().push(from);
+            }
+        }
+        let mut visited = BTreeSet::new();
+        let mut stack = vec![name];
+        while let Some(current) = stack.pop() {
+            for &from in reverse.get(current).into_iter().flatten() {
+                if visited.insert(from) {
+                    stack.push(from);
+                }
+            }
+        }
+        visited.remove(name);
+        visited
+    }
+
+    /// Strongly connected components of the include graph which form cycles.
+    pub fn cycles(&self) -> Vec<Vec<&str>> {
+        #[derive(Default)]
+        struct Tarjan<'s> {
+            index: BTreeMap<&'s str, usize>,
+            low: BTreeMap<&'s str, usize>,
+            stack: Vec<&'s str>,
+            on_stack: BTreeSet<&'s str>,
+            components: Vec<Vec<&'s str>>,
+        }
+        impl<'s> Tarjan<'s> {
+            fn connect(&mut self, map: &'s SnippetMap, v: &'s str) {
+                let index = self.index.len();
+                self.index.insert(v, index);
+                self.low.insert(v, index);
+                self.stack.push(v);
+                self.on_stack.insert(v);
+                for w in map.map[v].includes.iter().map(|s| s.as_str()) {
+                    if !map.map.contains_key(w) {
+                        continue;
+                    }
+                    if !self.index.contains_key(w) {
+                        self.connect(map, w);
+                        let low = self.low[v].min(self.low[w]);
+                        self.low.insert(v, low);
+                    } else if self.on_stack.contains(w) {
+                        let low = self.low[v].min(self.index[w]);
+                        self.low.insert(v, low);
+                    }
+                }
+                if self.low[v] == self.index[v] {
+                    let mut component = Vec::new();
+                    while let Some(w) = self.stack.pop() {
+                        self.on_stack.remove(w);
+                        component.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    let is_cycle = component.len() > 1 || map.map[v].includes.contains(v);
+                    if is_cycle {
+                        component.sort_unstable();
+                        self.components.push(component);
+                    }
+                }
+            }
+        }
+
+        let mut tarjan = Tarjan::default();
+        for name in self.map.keys() {
+            if !tarjan.index.contains_key(name.as_str()) {
+                tarjan.connect(self, name);
+            }
+        }
+        tarjan.components.sort_unstable();
+        tarjan.components
+    }
+}
+
+#[test]
+fn test_graph() {
+    let mut map = SnippetMap::new();
+    for (name, includes) in [
+        ("a", &["b"][..]),
+        ("b", &["c"]),
+        ("c", &["a", "d"]),
+        ("d", &[]),
+        ("e", &["e", "d"]),
+    ] {
+        map.map.insert(name.to_string(), Default::default());
+        map.map
+            .get_mut(name)
+            .unwrap()
+            .push_includes(includes.iter().map(|s| s.to_string()));
+    }
+    assert_eq!(map.includes_of("a"), BTreeSet::from(["b", "c", "d"]));
+    assert_eq!(map.includes_of("e"), BTreeSet::from(["d"]));
+    assert_eq!(map.included_by("a"), BTreeSet::from(["b", "c"]));
+    assert_eq!(map.includes_of("d"), BTreeSet::new());
+    assert_eq!(map.included_by("d"), BTreeSet::from(["a", "b", "c", "e"]));
+    assert_eq!(map.cycles(), vec![vec!["a", "b", "c"], vec!["e"]]);
+}
@@ -0,0 +1,401 @@
+use crate::{
+    ItemExt as _,
+    entry::{Entry, parse_entries},
+    map::{Filter, SnippetMap},
+};
+use proc_macro2::{TokenStream, TokenTree};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+use syn::{
+    ConstParam, Item, ItemMod, ItemUse, Macro, PatIdent, Path, TypeParam, UseTree,
+    visit::{self, Visit},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IncludeMode {
+    #[default]
+    Explicit,
+    Inferred,
+    Both,
+}
+
+impl FromStr for IncludeMode {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "explicit" => Ok(Self::Explicit),
+            "inferred" => Ok(Self::Inferred),
+            "both" => Ok(Self::Both),
+            _ => Err("expected one of [explicit|inferred|both]"),
+        }
+    }
+}
+
+impl IncludeMode {
+    pub const POSSIBLE_VALUES: [&'static str; 3] = ["explicit", "inferred", "both"];
+}
+
+/// Difference between the declared includes of a snippet and the inferred ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeConflict {
+    pub name: String,
+    /// Inferred but not declared.
+    pub undeclared: BTreeSet<String>,
+    /// Declared but not inferred.
+    pub unused: BTreeSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct EntryNames {
+    defs: BTreeSet<String>,
+    uses: BTreeSet<String>,
+}
+
+struct InferEntries<'a, 'i> {
+    entries: BTreeMap<String, EntryNames>,
+    filter: Filter<'a, 'i>,
+}
+
+#[derive(Default)]
+struct CollectUses {
+    uses: BTreeSet<String>,
+    /// Single identifiers, which may refer to local bindings.
+    idents: BTreeSet<String>,
+    locals: BTreeSet<String>,
+}
+
+impl SnippetMap {
+    /// Infer includes of each entry in `items` from the names it uses, and update includes
+    /// according to `mode`.
+    ///
+    /// Declared includes of snippets not defined in `items`, such as qualified names of other
+    /// sources, can not be inferred and are kept.
+    ///
+    /// Returns snippets whose declared includes differ from the inferred ones.
+    pub fn infer_includes(
+        &mut self,
+        items: &[Item],
+        filter: Filter,
+        mode: IncludeMode,
+    ) -> Vec<IncludeConflict> {
+        if mode == IncludeMode::Explicit {
+            return Vec::new();
+        }
+        let inferred_all = infer_includes(items, filter);
+        let mut conflicts = Vec::new();
+        for (name, link) in self.map.iter_mut() {
+            let Some(inferred) = inferred_all.get(name) else {
+                continue;
+            };
+            let (declared, kept): (BTreeSet<_>, BTreeSet<_>) = link
+                .includes
+                .iter()
+                .cloned()
+                .partition(|include| inferred_all.contains_key(include));
+            if !link.includes.is_empty() && &declared != inferred {
+                conflicts.push(IncludeConflict {
+                    name: name.clone(),
+                    undeclared: inferred.difference(&link.includes).cloned().collect(),
+                    unused: declared.difference(inferred).cloned().collect(),
+                });
+            }
+            match mode {
+                IncludeMode::Inferred => link.includes = inferred | &kept,
+                IncludeMode::Both => link.includes.extend(inferred.iter().cloned()),
+                IncludeMode::Explicit => {}
+            }
+        }
+        conflicts
+    }
+}
+
+/// Resolve names used by each entry to the entries that define them.
+pub fn infer_includes(items: &[Item], filter: Filter) -> BTreeMap<String, BTreeSet<String>> {
+    let mut collector = InferEntries {
+        entries: BTreeMap::new(),
+        filter,
+    };
+    for item in items {
+        collector.visit_item(item);
+    }
+
+    let mut definers: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (name, names) in collector.entries.iter() {
+        for def in names.defs.iter() {
+            definers.entry(def).or_default().insert(name);
+        }
+    }
+    collector
+        .entries
+        .iter()
+        .map(|(name, names)| {
+            let includes = names
+                .uses
+                .difference(&names.defs)
+                .filter_map(|used| definers.get(used.as_str()))
+                .flatten()
+                .filter(|&&include| include != name)
+                .map(|include| include.to_string())
+                .collect();
+            (name.clone(), includes)
+        })
+        .collect()
+}
+
+impl InferEntries<'_, '_> {
+    fn push_item(&mut self, entry: &Entry, item: &Item) {
+        if let Some(item) = self.filter.modify_item(item.clone()) {
+            let names = self.entries.entry(entry.name.clone()).or_default();
+            collect_defs(&item, &mut names.defs);
+            let mut uses = CollectUses::default();
+            uses.visit_item(&item);
+            names.uses.append(&mut uses.into_uses());
+        }
+    }
+}
+
+impl Visit<'_> for InferEntries<'_, '_> {
+    fn visit_item(&mut self, item: &Item) {
+        for entry in parse_entries(item) {
+            match (entry.inline, item) {
+                (true, Item::Mod(ItemMod { attrs, content, .. })) => {
+                    if !self.filter.is_skip_item(attrs)
+                        && let Some((_, items)) = content
+                    {
+                        for item in items {
+                            self.push_item(&entry, item);
+                        }
+                    }
+                }
+                _ => self.push_item(&entry, item),
+            }
+        }
+        visit::visit_item(self, item);
+    }
+}
+
+fn collect_defs(item: &Item, defs: &mut BTreeSet<String>) {
+    if let Some(name) = item.get_default_name()
+        && !matches!(item, Item::ExternCrate(_))
+    {
+        defs.insert(name);
+    }
+    if let Item::Mod(ItemMod {
+        content: Some((_, items)),
+        ..
+    }) = item
+    {
+        for item in items {
+            collect_defs(item, defs);
+        }
+    }
+}
+
+fn is_extern_root(ident: &str) -> bool {
+    matches!(ident, "std" | "core" | "alloc")
+}
+
+/// Whether a path starting with `ident` names an associated item of a type that is not an entry.
+fn is_type_root(ident: &str) -> bool {
+    matches!(
+        ident,
+        "Self"
+            | "bool"
+            | "char"
+            | "str"
+            | "f32"
+            | "f64"
+            | "i8"
+            | "i16"
+            | "i32"
+            | "i64"
+            | "i128"
+            | "isize"
+            | "u8"
+            | "u16"
+            | "u32"
+            | "u64"
+            | "u128"
+            | "usize"
+    )
+}
+
+impl CollectUses {
+    fn into_uses(self) -> BTreeSet<String> {
+        let mut uses = self.uses;
+        uses.extend(self.idents.difference(&self.locals).cloned());
+        uses
+    }
+
+    fn push_path(&mut self, path: &Path) {
+        if path.leading_colon.is_some()
+            || path
+                .segments
+                .first()
+                .is_some_and(|seg| is_extern_root(&seg.ident.to_string()))
+        {
+            return;
+        }
+        if let Some(ident) = path.get_ident() {
+            self.idents.insert(ident.to_string());
+            return;
+        }
+        // Only the first segment names an item in scope, the rest are its members.
+        if let Some(ident) = path
+            .segments
+            .iter()
+            .map(|seg| seg.ident.to_string())
+            .find(|ident| !matches!(ident.as_str(), "crate" | "self" | "super"))
+            && !is_type_root(&ident)
+        {
+            self.uses.insert(ident);
+        }
+    }
+
+    fn push_tokens(&mut self, tokens: TokenStream) {
+        for tt in tokens {
+            match tt {
+                TokenTree::Ident(ident) => {
+                    self.idents.insert(ident.to_string());
+                }
+                TokenTree::Group(group) => self.push_tokens(group.stream()),
+                _ => {}
+            }
+        }
+    }
+
+    fn push_use_tree(&mut self, tree: &UseTree, root: bool) {
+        match tree {
+            UseTree::Path(path) => {
+                let ident = path.ident.to_string();
+                if !(root && is_extern_root(&ident)) {
+                    self.uses.insert(ident);
+                    self.push_use_tree(&path.tree, false);
+                }
+            }
+            UseTree::Name(name) => {
+                self.uses.insert(name.ident.to_string());
+            }
+            UseTree::Rename(rename) => {
+                self.uses.insert(rename.ident.to_string());
+            }
+            UseTree::Glob(_) => {}
+            UseTree::Group(group) => {
+                for tree in group.items.iter() {
+                    self.push_use_tree(tree, root);
+                }
+            }
+        }
+    }
+}
+
+impl Visit<'_> for CollectUses {
+    fn visit_path(&mut self, path: &Path) {
+        self.push_path(path);
+        visit::visit_path(self, path);
+    }
+    fn visit_macro(&mut self, mac: &Macro) {
+        self.push_tokens(mac.tokens.clone());
+        visit::visit_macro(self, mac);
+    }
+    fn visit_pat_ident(&mut self, pat: &PatIdent) {
+        self.locals.insert(pat.ident.to_string());
+        visit::visit_pat_ident(self, pat);
+    }
+    fn visit_type_param(&mut self, param: &TypeParam) {
+        self.locals.insert(param.ident.to_string());
+        visit::visit_type_param(self, param);
+    }
+    fn visit_const_param(&mut self, param: &ConstParam) {
+        self.locals.insert(param.ident.to_string());
+        visit::visit_const_param(self, param);
+    }
+    fn visit_item_use(&mut self, item: &ItemUse) {
+        if item.leading_colon.is_none() {
+            self.push_use_tree(&item.tree, true);
+        }
+    }
+}
+
+#[test]
+fn test_infer_includes() {
+    let file = syn::parse_file(
+        r#"
+#[codesnip::entry]
+pub fn gcd(a: u64, b: u64) -> u64 { if b == 0 { a } else { gcd(b, a % b) } }
+#[codesnip::entry]
+pub fn lcm(a: u64, b: u64) -> u64 { a / gcd(a, b) * b }
+#[codesnip::entry(inline)]
+pub mod names { pub fn a() {} pub fn b() {} }
+#[codesnip::entry]
+pub struct Fraction(u64, u64);
+#[codesnip::entry("Fraction")]
+impl Fraction { pub fn reduce(self) -> Self { let g = lcm(1, gcd(self.0, self.1)); Self(self.0 / g, self.1 / g) } }
+#[codesnip::entry]
+pub fn swap() { std::mem::swap(&mut 0, &mut 1); println!("{:?}", Fraction(1, 2)); }
+#[codesnip::entry]
+pub fn max() {}
+#[codesnip::entry]
+pub fn new() {}
+#[codesnip::entry]
+pub fn f(x: Vec<u64>) -> u64 {
+    let _ = Vec::<u64>::new();
+    u64::max(1, x.iter().copied().fold(0, u64::max)) + crate::gcd(1, 2) + self::lcm(1, 2)
+}
+#[codesnip::entry("Fraction")]
+impl Fraction { pub fn new() -> Self { Self::from(Fraction(1, 1)) } fn from(x: Self) -> Self { x } }
+"#,
+    )
+    .unwrap();
+    let inferred = infer_includes(&file.items, Filter::new(&[], &[]));
+    let set = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>();
+    assert_eq!(inferred["gcd"], set(&[]));
+    assert_eq!(inferred["lcm"], set(&["gcd"]));
+    assert_eq!(inferred["Fraction"], set(&["gcd", "lcm"]));
+    assert_eq!(inferred["swap"], set(&["Fraction"]));
+    assert_eq!(inferred["f"], set(&["gcd", "lcm"]));
+}
+
+#[test]
+fn test_infer_includes_mode() {
+    let file = syn::parse_file(
+        r#"
+#[codesnip::entry]
+pub fn x() {}
+#[codesnip::entry(include("one::x", "z"))]
+pub fn y() { x(); }
+#[codesnip::entry(include("x"))]
+pub fn z() {}
+"#,
+    )
+    .unwrap();
+    let set = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>();
+    for (mode, includes) in [
+        (IncludeMode::Inferred, set(&["one::x", "x"])),
+        (IncludeMode::Both, set(&["one::x", "x", "z"])),
+    ] {
+        let mut map = SnippetMap::new();
+        for item in file.items.iter() {
+            map.extend_with_filter(item, Filter::new(&[], &[]));
+        }
+        let conflicts = map.infer_includes(&file.items, Filter::new(&[], &[]), mode);
+        assert_eq!(
+            conflicts,
+            [
+                IncludeConflict {
+                    name: "y".to_string(),
+                    undeclared: set(&["x"]),
+                    unused: set(&["z"]),
+                },
+                IncludeConflict {
+                    name: "z".to_string(),
+                    undeclared: set(&[]),
+                    unused: set(&["x"]),
+                },
+            ]
+        );
+        assert_eq!(map.map["y"].includes, includes);
+    }
+}
@@ -1,10 +1,19 @@
+mod cfg;
 pub mod entry;
 mod ext;
 mod format;
+mod graph;
+mod infer;
 mod map;
 mod parse;
+mod print;
 
+pub use cfg::CfgSet;
 pub use ext::{AttributeExt, ItemExt, PathExt};
-pub use format::{FormatOption, rustfmt_exits};
-pub use map::{Filter, LinkedSnippet, SnippetMap};
-pub use parse::{Error, parse_file_recursive};
+pub use format::{FormatOption, minify, rustfmt_exits};
+pub use infer::{IncludeConflict, IncludeMode, infer_includes};
+pub use map::{
+    BundleOrder, CollisionPolicy, Filter, LinkedSnippet, Provenance, SnippetMap, UnresolvedInclude,
+    find_guards, prefixed_name,
+};
+pub use parse::{Error, SourceFiles, parse_file_recursive, parse_file_recursive_with_files};
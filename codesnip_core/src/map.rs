@@ -1,19 +1,27 @@
 use crate::{
-    AttributeExt as _, ItemExt as _, PathExt as _, entry::EntryArgs, format::FormatOption,
+    Error, ItemExt as _, PathExt as _, SourceFiles, entry::parse_entries, format::FormatOption,
+    print::print_source,
 };
+use proc_macro2::{Span, TokenStream};
 use quote::ToTokens as _;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{BTreeMap, BTreeSet, VecDeque, btree_map},
+    fmt,
     iter::FromIterator,
+    path::PathBuf,
+    str::FromStr,
 };
 use syn::{
     Attribute, Item, ItemMod, Path,
-    parse::Parse as _,
+    spanned::Spanned as _,
     visit::{self, Visit},
 };
 
+const GUARD_PREFIX: &str = "// codesnip-guard: ";
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct SnippetMap {
     pub map: BTreeMap<String, LinkedSnippet>,
 }
@@ -22,12 +30,81 @@ pub struct SnippetMap {
 pub struct LinkedSnippet {
     pub contents: String,
     pub includes: BTreeSet<String>,
+    /// Position in declaration order.
+    #[serde(default)]
+    pub order: usize,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Other names to find this snippet by.
+    #[serde(default)]
+    pub aliases: BTreeSet<String>,
+    /// Deprecation note, empty if deprecated without one.
+    #[serde(default)]
+    pub deprecated: Option<String>,
+    /// Where each item of the contents came from.
+    #[serde(default)]
+    pub provenance: Vec<Provenance>,
+}
+
+/// Where an item of a snippet came from.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Empty if unknown.
+    pub path: PathBuf,
+    /// First and last lines, 1-based.
+    pub lines: (usize, usize),
+    /// Source config entry.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Commit checked out for a git source.
+    #[serde(default)]
+    pub commit: Option<String>,
+}
+
+/// An include missing from the map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnresolvedInclude {
+    pub include: String,
+    /// Snippets from the requested one to the one that includes it, empty if requested directly.
+    pub chain: Vec<String>,
+}
+
+/// Order of snippets in a bundle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BundleOrder {
+    /// Requested snippets, then includes by name.
+    #[default]
+    Alphabetical,
+    /// Requested snippets, then includes in declaration order.
+    Declaration,
+    /// Includes before snippets including them.
+    DependenciesFirst,
+    /// Includes after snippets including them.
+    DependenciesLast,
+}
+
+/// How to merge snippets of the same name from different maps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    Error,
+    /// Report and append.
+    #[default]
+    Warn,
+    FirstWins,
+    LastWins,
+    Append,
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct Filter<'a, 'i> {
     filter_attr: &'a [Path],
     filter_item: &'i [Path],
+    preserve_source: bool,
+    files: Option<&'a SourceFiles>,
 }
 
 struct CollectEntries<'m, 'i, 'a> {
@@ -41,16 +118,74 @@ impl SnippetMap {
     }
     fn get_mut(&mut self, name: &str) -> &mut LinkedSnippet {
         if !self.map.contains_key(name) {
-            self.map.insert(name.to_string(), Default::default());
+            let order = self.next_order();
+            self.map.insert(
+                name.to_string(),
+                LinkedSnippet {
+                    order,
+                    ..Default::default()
+                },
+            );
         }
         self.map
             .get_mut(name)
             .expect("BTreeMap is not working properly.")
     }
+    fn next_order(&self) -> usize {
+        self.map
+            .values()
+            .map(|link| link.order + 1)
+            .max()
+            .unwrap_or_default()
+    }
+    /// Find a snippet by name or alias, a name takes precedence over aliases.
+    pub fn resolve(&self, name: &str) -> Option<(&str, &LinkedSnippet)> {
+        self.map
+            .get_key_value(name)
+            .or_else(|| {
+                self.map
+                    .iter()
+                    .find(|(_, link)| link.aliases.contains(name))
+            })
+            .map(|(name, link)| (name.as_str(), link))
+    }
+    /// Replace aliases in includes with the names they refer to.
+    pub fn resolve_aliases(&mut self) {
+        let names: BTreeMap<String, String> = self
+            .map
+            .values()
+            .flat_map(|link| link.aliases.iter())
+            .filter_map(|alias| {
+                let (name, _) = self.resolve(alias)?;
+                Some((alias.clone(), name.to_string()))
+            })
+            .collect();
+        for link in self.map.values_mut() {
+            link.includes = link
+                .includes
+                .iter()
+                .map(|include| names.get(include).unwrap_or(include).clone())
+                .collect();
+        }
+    }
+    /// Aliases which are also snippet names or aliases of several snippets, with the snippets
+    /// declaring them.
+    pub fn alias_collisions(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut declared: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (name, link) in self.map.iter() {
+            for alias in link.aliases.iter() {
+                declared.entry(alias).or_default().push(name);
+            }
+        }
+        declared
+            .into_iter()
+            .filter(|(alias, names)| names.len() > 1 || self.map.contains_key(*alias))
+            .collect()
+    }
     pub fn extend_with_filter(&mut self, item: &Item, filter: Filter) {
         CollectEntries { map: self, filter }.visit_item(item);
     }
-    fn resolve_includes<'s>(
+    pub(crate) fn resolve_includes<'s>(
         &'s self,
         used: &BTreeSet<&'s str>,
         includes: impl IntoIterator<Item = &'s str>,
@@ -70,42 +205,318 @@ impl SnippetMap {
         }
         visited
     }
+    /// Bundle a snippet with its includes, includes missing from the map are skipped.
     pub fn bundle<'s>(
-        &self,
+        &'s self,
         name: &'s str,
-        link: &LinkedSnippet,
+        link: &'s LinkedSnippet,
+        excludes: BTreeSet<&'s str>,
+        guard: bool,
+        order: BundleOrder,
+    ) -> String {
+        self.bundle_links([(name, link)], excludes, guard, order)
+    }
+    /// Same as [`SnippetMap::bundle`], but fails if any include is missing from the map.
+    pub fn try_bundle<'s>(
+        &'s self,
+        name: &'s str,
+        link: &'s LinkedSnippet,
+        excludes: BTreeSet<&'s str>,
+        guard: bool,
+        order: BundleOrder,
+    ) -> Result<String, Error> {
+        let unresolved = self.unresolved_links([(name, link)], &excludes);
+        if !unresolved.is_empty() {
+            return Err(Error::UnresolvedIncludes(unresolved));
+        }
+        Ok(self.bundle_links([(name, link)], excludes, guard, order))
+    }
+    /// Bundle several snippets into one, each included snippet appears only once.
+    ///
+    /// Fails on names missing from the map unless `lenient`.
+    pub fn bundle_names<'s>(
+        &'s self,
+        names: impl IntoIterator<Item = &'s str>,
+        excludes: BTreeSet<&'s str>,
+        guard: bool,
+        lenient: bool,
+        order: BundleOrder,
+    ) -> Result<String, Error> {
+        let mut unresolved = Vec::new();
+        let mut links = Vec::new();
+        for name in names {
+            match self.resolve(name) {
+                Some(link) => links.push(link),
+                None if excludes.contains(name) => {}
+                None => unresolved.push(UnresolvedInclude {
+                    include: name.to_string(),
+                    chain: Vec::new(),
+                }),
+            }
+        }
+        if !lenient {
+            unresolved.extend(self.unresolved_links(links.iter().cloned(), &excludes));
+            if !unresolved.is_empty() {
+                return Err(Error::UnresolvedIncludes(unresolved));
+            }
+        }
+        Ok(self.bundle_links(links, excludes, guard, order))
+    }
+    /// Append snippets to `contents`, except ones already bundled into it.
+    pub fn bundle_into<'s>(
+        &'s self,
+        contents: &mut String,
+        names: impl IntoIterator<Item = &'s str>,
+        excludes: BTreeSet<&'s str>,
+        lenient: bool,
+        order: BundleOrder,
+    ) -> Result<(), Error> {
+        let guards: Vec<String> = find_guards(contents)
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+        let names: Vec<&str> = names.into_iter().collect();
+        let mut excludes: BTreeSet<&str> = excludes;
+        excludes.extend(guards.iter().map(|s| s.as_str()));
+        let bundled = self.bundle_names(names, excludes, true, lenient, order)?;
+        if !bundled.is_empty() && !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str(&bundled);
+        Ok(())
+    }
+    /// Deprecated snippets bundled with `names` except `excludes`, with their deprecation notes.
+    pub fn deprecated_in<'s>(
+        &'s self,
+        names: impl IntoIterator<Item = &'s str>,
+        excludes: &BTreeSet<&'s str>,
+    ) -> Vec<(&'s str, &'s str)> {
+        let bundled = self.resolve_includes(excludes, names);
+        bundled
+            .difference(excludes)
+            .filter_map(|name| {
+                let note = self.map.get(*name)?.deprecated.as_deref()?;
+                Some((*name, note))
+            })
+            .collect()
+    }
+    /// All includes missing from the map.
+    pub fn unresolved_includes(&self) -> Vec<UnresolvedInclude> {
+        self.map
+            .iter()
+            .flat_map(|(name, link)| {
+                link.includes
+                    .iter()
+                    .filter(|include| !self.map.contains_key(*include))
+                    .map(|include| UnresolvedInclude {
+                        include: include.clone(),
+                        chain: vec![name.clone()],
+                    })
+            })
+            .collect()
+    }
+    /// Includes missing from the map, reachable from `links` without passing `excludes`.
+    fn unresolved_links<'s>(
+        &'s self,
+        links: impl IntoIterator<Item = (&'s str, &'s LinkedSnippet)>,
+        excludes: &BTreeSet<&'s str>,
+    ) -> Vec<UnresolvedInclude> {
+        let mut visited = excludes.clone();
+        let mut parent: BTreeMap<&str, &str> = BTreeMap::new();
+        let mut queue: VecDeque<(&str, &LinkedSnippet)> = links
+            .into_iter()
+            .filter(|(name, _)| visited.insert(name))
+            .collect();
+        let mut unresolved = Vec::new();
+        while let Some((name, link)) = queue.pop_front() {
+            for include in link.includes.iter().map(|s| s.as_str()) {
+                match self.map.get(include) {
+                    Some(nlink) => {
+                        if visited.insert(include) {
+                            parent.insert(include, name);
+                            queue.push_back((include, nlink));
+                        }
+                    }
+                    None => {
+                        let mut chain = vec![name.to_string()];
+                        let mut current = name;
+                        while let Some(&next) = parent.get(current) {
+                            chain.push(next.to_string());
+                            current = next;
+                        }
+                        chain.reverse();
+                        unresolved.push(UnresolvedInclude {
+                            include: include.to_string(),
+                            chain,
+                        });
+                    }
+                }
+            }
+        }
+        unresolved
+    }
+    fn bundle_links<'s>(
+        &'s self,
+        links: impl IntoIterator<Item = (&'s str, &'s LinkedSnippet)>,
         mut excludes: BTreeSet<&'s str>,
         guard: bool,
+        order: BundleOrder,
     ) -> String {
         fn push_guard(contents: &mut String, name: &str) {
             if contents.chars().next_back().is_some_and(|ch| ch != '\n') {
                 contents.push('\n');
             }
-            contents.push_str("// codesnip-guard: ");
+            contents.push_str(GUARD_PREFIX);
             contents.push_str(name);
             contents.push('\n');
         }
 
-        if excludes.contains(name) {
-            return Default::default();
-        }
-        excludes.insert(name);
-        let visited = self.resolve_includes(&excludes, link.includes.iter().map(|s| s.as_str()));
+        let links: Vec<_> = links
+            .into_iter()
+            .filter(|(name, _)| excludes.insert(name))
+            .collect();
+        let visited = self.resolve_includes(
+            &excludes,
+            links
+                .iter()
+                .flat_map(|(_, link)| link.includes.iter().map(|s| s.as_str())),
+        );
+        let includes: BTreeSet<&str> = visited.difference(&excludes).cloned().collect();
         let mut contents = String::new();
-        if guard {
-            push_guard(&mut contents, name);
-        }
-        contents.push_str(link.contents.as_str());
-        for include in visited.difference(&excludes).cloned() {
+        for name in self.bundle_order(&links, includes, order) {
             if guard {
-                push_guard(&mut contents, include);
+                push_guard(&mut contents, name);
             }
-            if let Some(nlink) = self.map.get(include) {
-                contents.push_str(nlink.contents.as_str());
+            let link = links
+                .iter()
+                .find(|(root, _)| *root == name)
+                .map(|(_, link)| *link)
+                .or_else(|| self.map.get(name));
+            if let Some(link) = link {
+                contents.push_str(link.contents.as_str());
             }
         }
         contents
     }
+    /// Order of bundled snippets, roots are `links` and the rest are `includes`.
+    fn bundle_order<'s>(
+        &'s self,
+        links: &[(&'s str, &'s LinkedSnippet)],
+        includes: BTreeSet<&'s str>,
+        order: BundleOrder,
+    ) -> Vec<&'s str> {
+        let declared = |name: &&str| self.map.get(*name).map(|link| link.order);
+        match order {
+            BundleOrder::Alphabetical => links
+                .iter()
+                .map(|(name, _)| *name)
+                .chain(includes)
+                .collect(),
+            BundleOrder::Declaration => {
+                let mut includes: Vec<_> = includes.into_iter().collect();
+                includes.sort_by_key(|name| (declared(name).is_none(), declared(name)));
+                links
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .chain(includes)
+                    .collect()
+            }
+            BundleOrder::DependenciesFirst | BundleOrder::DependenciesLast => {
+                fn visit<'s>(
+                    map: &'s SnippetMap,
+                    name: &'s str,
+                    link: Option<&'s LinkedSnippet>,
+                    nodes: &BTreeSet<&'s str>,
+                    seen: &mut BTreeSet<&'s str>,
+                    out: &mut Vec<&'s str>,
+                ) {
+                    if !seen.insert(name) {
+                        return;
+                    }
+                    if let Some(link) = link {
+                        let mut children: Vec<_> = link
+                            .includes
+                            .iter()
+                            .map(|s| s.as_str())
+                            .filter(|include| nodes.contains(include))
+                            .collect();
+                        children.sort_by_key(|name| {
+                            let order = map.map.get(*name).map(|link| link.order);
+                            (order.is_none(), order)
+                        });
+                        for child in children {
+                            visit(map, child, map.map.get(child), nodes, seen, out);
+                        }
+                    }
+                    out.push(name);
+                }
+
+                let mut nodes = includes;
+                nodes.extend(links.iter().map(|(name, _)| *name));
+                let mut seen = BTreeSet::new();
+                let mut out = Vec::new();
+                for (name, link) in links {
+                    visit(self, name, Some(link), &nodes, &mut seen, &mut out);
+                }
+                if order == BundleOrder::DependenciesLast {
+                    out.reverse();
+                }
+                out
+            }
+        }
+    }
+    /// Add `prefix` to names and includes.
+    ///
+    /// An include `other::name` refers to `name` in a source with prefix `other`, and `::name` to
+    /// one without prefix.
+    pub fn prefixed(self, prefix: Option<&str>) -> Self {
+        self.into_iter()
+            .map(|(name, mut link)| {
+                link.includes = link
+                    .includes
+                    .iter()
+                    .map(|include| match include.rsplit_once("::") {
+                        Some((other, include)) => prefixed_name(Some(other), include),
+                        None => prefixed_name(prefix, include),
+                    })
+                    .collect();
+                link.aliases = link
+                    .aliases
+                    .iter()
+                    .map(|alias| prefixed_name(prefix, alias))
+                    .collect();
+                (prefixed_name(prefix, &name), link)
+            })
+            .collect()
+    }
+    /// Merge `other` into `self` and return names defined in both.
+    ///
+    /// Snippets of `other` are ordered after snippets of `self` in declaration order.
+    pub fn merge(&mut self, other: SnippetMap, policy: CollisionPolicy) -> Vec<String> {
+        let mut collisions = Vec::new();
+        let offset = self.next_order();
+        for (name, mut link) in other {
+            link.order += offset;
+            match self.map.entry(name) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(link);
+                }
+                btree_map::Entry::Occupied(mut entry) => {
+                    collisions.push(entry.key().clone());
+                    match policy {
+                        CollisionPolicy::FirstWins => {}
+                        CollisionPolicy::LastWins => {
+                            entry.insert(link);
+                        }
+                        CollisionPolicy::Error
+                        | CollisionPolicy::Warn
+                        | CollisionPolicy::Append => entry.get_mut().append(link),
+                    }
+                }
+            }
+        }
+        collisions
+    }
     pub fn keys(&self, hide: bool) -> Vec<&str> {
         if hide {
             self.map
@@ -119,6 +530,29 @@ impl SnippetMap {
     }
 }
 
+/// `name` as [`SnippetMap::prefixed`] renames it, unchanged if `prefix` is empty.
+pub fn prefixed_name(prefix: Option<&str>, name: &str) -> String {
+    match prefix {
+        Some(prefix) if !prefix.is_empty() => format!("{}_{}", prefix, name),
+        _ => name.to_string(),
+    }
+}
+
+/// Names of snippets already bundled into `contents`, found by `// codesnip-guard: NAME` lines.
+pub fn find_guards(contents: &str) -> BTreeSet<&str> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            line.trim_start()
+                .strip_prefix("//")?
+                .trim_start()
+                .strip_prefix("codesnip-guard:")
+        })
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
 impl IntoIterator for SnippetMap {
     type Item = (String, LinkedSnippet);
     type IntoIter = <BTreeMap<String, LinkedSnippet> as IntoIterator>::IntoIter;
@@ -130,7 +564,12 @@ impl IntoIterator for SnippetMap {
 impl Extend<(String, LinkedSnippet)> for SnippetMap {
     fn extend<T: IntoIterator<Item = (String, LinkedSnippet)>>(&mut self, iter: T) {
         for (name, link) in iter {
-            self.map.entry(name).or_default().append(link);
+            match self.map.entry(name) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(link);
+                }
+                btree_map::Entry::Occupied(mut entry) => entry.get_mut().append(link),
+            }
         }
     }
 }
@@ -148,9 +587,37 @@ impl LinkedSnippet {
         self.contents.push_str(contents);
     }
     pub fn push_item_with_filter(&mut self, item: &Item, filter: Filter) {
+        let start = item.span();
         if let Some(item) = filter.modify_item(item.clone()) {
-            self.contents
-                .push_str(&item.into_token_stream().to_string());
+            if filter.preserve_source {
+                self.contents
+                    .push_str(&print_source(item.into_token_stream(), Some(start)));
+                self.contents.push('\n');
+            } else {
+                self.contents
+                    .push_str(&item.into_token_stream().to_string());
+            }
+        }
+    }
+    pub fn push_items_with_filter(&mut self, items: &[Item], filter: Filter) {
+        self.push_items_from(None, items, filter);
+    }
+    /// Same as [`LinkedSnippet::push_items_with_filter`], keeping comments after `start` like
+    /// those at the beginning of a module.
+    fn push_items_from(&mut self, start: Option<Span>, items: &[Item], filter: Filter) {
+        if filter.preserve_source {
+            let start = start.or_else(|| items.first().map(|item| item.span()));
+            let tokens: TokenStream = items
+                .iter()
+                .filter_map(|item| filter.modify_item(item.clone()))
+                .map(|item| item.into_token_stream())
+                .collect();
+            self.contents.push_str(&print_source(tokens, start));
+            self.contents.push('\n');
+        } else {
+            for item in items {
+                self.push_item_with_filter(item, filter);
+            }
         }
     }
     pub fn push_include(&mut self, include: String) {
@@ -159,9 +626,30 @@ impl LinkedSnippet {
     pub fn push_includes(&mut self, includes: impl IntoIterator<Item = String>) {
         self.includes.extend(includes);
     }
+    /// Add metadata, a description or category already set is kept.
+    pub fn push_metadata(
+        &mut self,
+        description: Option<String>,
+        tags: impl IntoIterator<Item = String>,
+        category: Option<String>,
+    ) {
+        if self.description.is_none() {
+            self.description = description;
+        }
+        self.tags.extend(tags);
+        if self.category.is_none() {
+            self.category = category;
+        }
+    }
     pub fn append(&mut self, mut other: Self) {
         self.contents.push_str(&other.contents);
         self.includes.append(&mut other.includes);
+        self.push_metadata(other.description, other.tags, other.category);
+        self.aliases.append(&mut other.aliases);
+        if self.deprecated.is_none() {
+            self.deprecated = other.deprecated;
+        }
+        self.provenance.append(&mut other.provenance);
     }
     pub fn format(&mut self, option: &FormatOption) -> bool {
         if let Some(formatted) = option.format(&self.contents) {
@@ -173,39 +661,121 @@ impl LinkedSnippet {
     }
 }
 
+impl fmt::Display for UnresolvedInclude {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`", self.include)?;
+        if !self.chain.is_empty() {
+            write!(f, " required by ")?;
+            for (i, name) in self.chain.iter().enumerate() {
+                if i != 0 {
+                    write!(f, " -> ")?;
+                }
+                write!(f, "`{}`", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.lines {
+            (start, end) if start == end => write!(f, "{}:{}", self.path.display(), start)?,
+            (start, end) => write!(f, "{}:{}-{}", self.path.display(), start, end)?,
+        }
+        if let Some(source) = &self.source {
+            write!(f, " from {}", source)?;
+        }
+        if let Some(commit) = &self.commit {
+            write!(f, " at {}", commit)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BundleOrder {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alphabetical" => Ok(Self::Alphabetical),
+            "declaration" => Ok(Self::Declaration),
+            "deps-first" => Ok(Self::DependenciesFirst),
+            "deps-last" => Ok(Self::DependenciesLast),
+            _ => Err("expected one of [alphabetical|declaration|deps-first|deps-last]"),
+        }
+    }
+}
+
+impl BundleOrder {
+    pub const POSSIBLE_VALUES: [&'static str; 4] =
+        ["alphabetical", "declaration", "deps-first", "deps-last"];
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "first-wins" => Ok(Self::FirstWins),
+            "last-wins" => Ok(Self::LastWins),
+            "append" => Ok(Self::Append),
+            _ => Err("expected one of [error|warn|first-wins|last-wins|append]"),
+        }
+    }
+}
+
+impl CollisionPolicy {
+    pub const POSSIBLE_VALUES: [&'static str; 5] =
+        ["error", "warn", "first-wins", "last-wins", "append"];
+}
+
 impl<'a, 'i> Filter<'a, 'i> {
     pub fn new(filter_attr: &'a [Path], filter_item: &'i [Path]) -> Self {
         Self {
             filter_attr,
             filter_item,
+            preserve_source: false,
+            files: None,
+        }
+    }
+    /// Take contents from the original source text instead of re-printing tokens.
+    pub fn preserve_source(self, preserve_source: bool) -> Self {
+        Self {
+            preserve_source,
+            ..self
+        }
+    }
+    /// Files to locate items in for [`LinkedSnippet::provenance`].
+    pub fn source_files(self, files: &'a SourceFiles) -> Self {
+        Self {
+            files: Some(files),
+            ..self
         }
     }
 }
 
 impl Visit<'_> for CollectEntries<'_, '_, '_> {
     fn visit_item(&mut self, item: &Item) {
-        if let Some(attrs) = item.get_attributes() {
-            for entry in attrs
-                .iter()
-                .filter(|attr| attr.path().is_codesnip_entry())
-                .filter_map(|attr| attr.parse_args_empty_with(EntryArgs::parse).ok())
-                .filter_map(|args| args.try_to_entry(item).ok())
-            {
-                let link = self.map.get_mut(&entry.name);
-                let filter = self.filter;
-                match (entry.inline, item) {
-                    (true, Item::Mod(ItemMod { attrs, content, .. })) => {
-                        if !filter.is_skip_item(attrs)
-                            && let Some((_, items)) = content
-                        {
-                            for item in items {
-                                link.push_item_with_filter(item, filter);
-                            }
-                        }
+        for entry in parse_entries(item) {
+            let link = self.map.get_mut(&entry.name);
+            let filter = self.filter;
+            match (entry.inline, item) {
+                (true, Item::Mod(ItemMod { attrs, content, .. })) => {
+                    if !filter.is_skip_item(attrs)
+                        && let Some((brace, items)) = content
+                    {
+                        link.push_items_from(Some(brace.span.open()), items, filter);
                     }
-                    _ => link.push_item_with_filter(item, filter),
                 }
-                link.push_includes(entry.include);
+                _ => link.push_item_with_filter(item, filter),
+            }
+            link.provenance.push(filter.provenance(item));
+            link.push_includes(entry.include);
+            link.push_metadata(entry.description, entry.tags, entry.category);
+            link.aliases.extend(entry.aliases);
+            if link.deprecated.is_none() {
+                link.deprecated = entry.deprecated;
             }
         }
         visit::visit_item(self, item);
@@ -213,12 +783,22 @@ impl Visit<'_> for CollectEntries<'_, '_, '_> {
 }
 
 impl Filter<'_, '_> {
-    fn is_skip_item(self, attrs: &[Attribute]) -> bool {
+    pub(crate) fn is_skip_item(self, attrs: &[Attribute]) -> bool {
         attrs.iter().any(|attr| {
             attr.path().is_codesnip_skip() || self.filter_item.iter().any(|pat| pat == attr.path())
         })
     }
 
+    fn provenance(self, item: &Item) -> Provenance {
+        let span = item.span();
+        let path = self.files.and_then(|files| files.locate(span));
+        Provenance {
+            path: path.map(ToOwned::to_owned).unwrap_or_default(),
+            lines: (span.start().line, span.end().line),
+            ..Default::default()
+        }
+    }
+
     fn filter_attributes(self, attrs: &mut Vec<Attribute>) {
         attrs.retain(|attr| {
             !(attr.path().is_codesnip_entry()
@@ -226,7 +806,7 @@ impl Filter<'_, '_> {
         })
     }
 
-    fn modify_item(self, mut item: Item) -> Option<Item> {
+    pub(crate) fn modify_item(self, mut item: Item) -> Option<Item> {
         if let Some(attrs) = item.get_attributes()
             && self.is_skip_item(attrs)
         {
@@ -251,3 +831,239 @@ impl Filter<'_, '_> {
         Some(item)
     }
 }
+
+#[test]
+fn test_find_guards() {
+    let contents = "fn main() {}\n// codesnip-guard: gcd\nfn gcd() {}\n    //   codesnip-guard: lcm  \n// codesnip-guard:\n";
+    assert_eq!(find_guards(contents), BTreeSet::from(["gcd", "lcm"]));
+}
+
+#[test]
+fn test_merge() {
+    let snippet = |contents: &str| {
+        let mut link = LinkedSnippet::default();
+        link.push_contents(contents);
+        link
+    };
+    let first: SnippetMap = [
+        ("a".to_string(), snippet("a1")),
+        ("b".to_string(), snippet("b1")),
+    ]
+    .into_iter()
+    .collect();
+    let second: SnippetMap = [
+        ("b".to_string(), snippet("b2")),
+        ("c".to_string(), snippet("c2")),
+    ]
+    .into_iter()
+    .collect();
+    for (policy, contents) in [
+        (CollisionPolicy::FirstWins, "b1"),
+        (CollisionPolicy::LastWins, "b2"),
+        (CollisionPolicy::Append, "b1b2"),
+    ] {
+        let mut map = first.clone();
+        assert_eq!(map.merge(second.clone(), policy), vec!["b".to_string()]);
+        assert_eq!(map.map["b"].contents, contents);
+        assert_eq!(map.map.len(), 3);
+    }
+}
+
+#[test]
+fn test_merge_declaration_order() {
+    let declare = |names: &[&str]| {
+        let mut map = SnippetMap::new();
+        for name in names {
+            map.get_mut(name).push_contents(name);
+        }
+        map
+    };
+    let mut map = declare(&["c", "a"]);
+    map.merge(declare(&["d", "b"]), CollisionPolicy::Warn);
+    map.get_mut("x")
+        .push_includes(["a", "b", "c", "d"].map(String::from));
+    let bundle = |map: &SnippetMap| {
+        map.bundle_names(
+            ["x"],
+            Default::default(),
+            false,
+            false,
+            BundleOrder::Declaration,
+        )
+        .unwrap()
+    };
+    assert_eq!(bundle(&map), "cadb");
+    let collected: SnippetMap = map.clone().into_iter().collect();
+    assert_eq!(bundle(&collected), "cadb");
+}
+
+#[test]
+fn test_prefixed() {
+    let mut link = LinkedSnippet::default();
+    link.push_includes(["a", "other::b", "::c"].map(String::from));
+    let map: SnippetMap = [("x".to_string(), link)].into_iter().collect();
+    let includes =
+        |map: &SnippetMap, name: &str| map.map[name].includes.iter().cloned().collect::<Vec<_>>();
+    let prefixed = map.clone().prefixed(Some("p"));
+    assert_eq!(includes(&prefixed, "p_x"), ["c", "other_b", "p_a"]);
+    let mut map = map;
+    map.get_mut("y").push_contents("y");
+    assert_eq!(map.clone().prefixed(Some("p")).map["p_y"].order, 1);
+    let unprefixed = map.prefixed(None);
+    assert_eq!(includes(&unprefixed, "x"), ["a", "c", "other_b"]);
+}
+
+#[test]
+fn test_unresolved_includes() {
+    let link = |includes: &[&str]| {
+        let mut link = LinkedSnippet::default();
+        link.push_includes(includes.iter().map(|s| s.to_string()));
+        link
+    };
+    let map: SnippetMap = [
+        ("a".to_string(), link(&["b", "x"])),
+        ("b".to_string(), link(&["c"])),
+        ("c".to_string(), link(&["y"])),
+    ]
+    .into_iter()
+    .collect();
+    let order = BundleOrder::default();
+    let Err(Error::UnresolvedIncludes(unresolved)) =
+        map.bundle_names(["a", "z"], Default::default(), true, false, order)
+    else {
+        panic!("expected unresolved includes");
+    };
+    assert_eq!(
+        unresolved.iter().map(|u| u.to_string()).collect::<Vec<_>>(),
+        [
+            "`z`",
+            "`x` required by `a`",
+            "`y` required by `a` -> `b` -> `c`"
+        ]
+    );
+    assert!(
+        map.bundle_names(["a"], ["b"].into(), true, false, order)
+            .is_err()
+    );
+    assert!(
+        map.bundle_names(["b"], ["c"].into(), true, false, order)
+            .is_ok()
+    );
+    assert!(
+        map.bundle_names(["a", "z"], Default::default(), true, true, order)
+            .is_ok()
+    );
+    assert_eq!(map.unresolved_includes().len(), 2);
+}
+
+#[test]
+fn test_bundle_order() {
+    let mut map = SnippetMap::new();
+    for (name, includes) in [
+        ("d", &["b"][..]),
+        ("c", &[]),
+        ("b", &["c"]),
+        ("a", &["d", "c"]),
+    ] {
+        let link = map.get_mut(name);
+        link.push_contents(name);
+        link.push_includes(includes.iter().map(|s| s.to_string()));
+    }
+    let bundle = |order| {
+        map.bundle_names(["a"], Default::default(), false, false, order)
+            .unwrap()
+    };
+    assert_eq!(bundle(BundleOrder::Alphabetical), "abcd");
+    assert_eq!(bundle(BundleOrder::Declaration), "adcb");
+    assert_eq!(bundle(BundleOrder::DependenciesFirst), "cbda");
+    assert_eq!(bundle(BundleOrder::DependenciesLast), "adbc");
+}
+
+#[test]
+fn test_aliases() {
+    let mut map = SnippetMap::new();
+    for (name, aliases, includes) in [
+        ("a", &["old_a", "b"][..], &["old_c"][..]),
+        ("b", &[], &[]),
+        ("c", &["old_c"], &["old_a"]),
+        ("d", &["old_c"], &[]),
+    ] {
+        let link = map.get_mut(name);
+        link.push_contents(name);
+        link.aliases.extend(aliases.iter().map(|s| s.to_string()));
+        link.push_includes(includes.iter().map(|s| s.to_string()));
+    }
+    assert_eq!(map.resolve("old_a").map(|(name, _)| name), Some("a"));
+    assert_eq!(map.resolve("b").map(|(name, _)| name), Some("b"));
+    assert_eq!(map.resolve("old_c").map(|(name, _)| name), Some("c"));
+    assert!(map.resolve("x").is_none());
+    assert_eq!(
+        map.alias_collisions(),
+        vec![("b", vec!["a"]), ("old_c", vec!["c", "d"])]
+    );
+    map.resolve_aliases();
+    assert_eq!(map.map["a"].includes, BTreeSet::from(["c".to_string()]));
+    map.map.get_mut("c").unwrap().deprecated = Some("use d".to_string());
+    assert_eq!(
+        map.deprecated_in(["a"], &Default::default()),
+        vec![("c", "use d")]
+    );
+    assert!(map.deprecated_in(["a"], &["c"].into()).is_empty());
+    let bundle = map
+        .bundle_names(
+            ["old_a"],
+            Default::default(),
+            false,
+            false,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(bundle, "ac");
+}
+
+#[test]
+fn test_deprecated() {
+    let file = syn::parse_file(
+        "#[codesnip::entry(deprecated = \"use b\")]\nfn a() {}\n#[codesnip::entry(deprecated)]\nfn b() {}\n#[codesnip::entry]\nfn c() {}\n",
+    )
+    .unwrap();
+    let mut map = SnippetMap::new();
+    for item in file.items.iter() {
+        map.extend_with_filter(item, Filter::new(&[], &[]));
+    }
+    assert_eq!(map.map["a"].deprecated.as_deref(), Some("use b"));
+    assert_eq!(map.map["b"].deprecated.as_deref(), Some(""));
+    assert_eq!(map.map["c"].deprecated, None);
+}
+
+#[test]
+fn test_provenance() {
+    let file = syn::parse_file(
+        "#[codesnip::entry]\nfn a() {}\n\n#[codesnip::entry(\"b\")]\nfn b() {\n}\n#[codesnip::entry(\"b\")]\nfn c() {}\n",
+    )
+    .unwrap();
+    let mut map = SnippetMap::new();
+    for item in file.items.iter() {
+        map.extend_with_filter(item, Filter::new(&[], &[]));
+    }
+    let lines = |name: &str| {
+        map.map[name]
+            .provenance
+            .iter()
+            .map(|provenance| provenance.lines)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(lines("a"), [(1, 2)]);
+    assert_eq!(lines("b"), [(4, 6), (7, 8)]);
+    assert_eq!(map.map["a"].provenance[0].to_string(), ":1-2");
+    let provenance = Provenance {
+        path: "src/lib.rs".into(),
+        lines: (3, 3),
+        source: Some("https://github.com/owner/repo.git:src/lib.rs".to_string()),
+        commit: Some("0123abcd".to_string()),
+    };
+    assert_eq!(
+        provenance.to_string(),
+        "src/lib.rs:3 from https://github.com/owner/repo.git:src/lib.rs at 0123abcd"
+    );
+}
@@ -1,12 +1,13 @@
-use crate::ItemExt as _;
+use crate::{CfgSet, ItemExt as _, UnresolvedInclude};
 use Error::{FileNotFound, ModuleNotFound, ParseFile};
-use proc_macro2::TokenStream;
+use proc_macro2::{Span, TokenStream};
 use quote::{ToTokens as _, quote};
 use std::path::{Path, PathBuf};
 use syn::{
     AttrStyle, Attribute, Expr, ExprLit, File, Item, ItemMod, Lit, Meta, MetaNameValue, Token,
     parse_file, parse2,
     punctuated::Punctuated,
+    spanned::Spanned as _,
     visit_mut::{self, VisitMut},
 };
 
@@ -20,6 +21,11 @@ pub enum Error {
     ModuleNotFound(String, PathBuf),
     #[error("File `{}` not found.", .0.display())]
     FileNotFound(PathBuf, #[source] std::io::Error),
+    #[error(
+        "Unresolved includes:{}",
+        .0.iter().map(|include| format!("\n  {}", include)).collect::<String>()
+    )]
+    UnresolvedIncludes(Vec<UnresolvedInclude>),
 }
 
 pub fn parse_file_recursive(
@@ -27,21 +33,57 @@ pub fn parse_file_recursive(
     cfg_enable: &[Meta],
     cfg_disable: &[Meta],
 ) -> Result<File, Error> {
+    let cfg = CfgSet::new(cfg_enable, cfg_disable);
+    parse_file_recursive_with_files(path, &cfg).map(|(ast, _)| ast)
+}
+
+/// Same as [`parse_file_recursive`] with `cfg` predicates evaluated by `cfg`, but also returns
+/// all files read.
+pub fn parse_file_recursive_with_files(
+    path: PathBuf,
+    cfg: &CfgSet,
+) -> Result<(File, SourceFiles), Error> {
     let mut mod_dir = path.clone();
     mod_dir.pop();
     let cwd = mod_dir.clone();
     let mut ext = ExtractAst {
         mod_dir,
         cwd,
+        files: Default::default(),
         error: None,
-        cfg_enable,
-        cfg_disable,
+        cfg,
     };
     let mut ast = parse_file_from_path(&path)?;
+    ext.files.push(path, &ast);
     ext.visit_file_mut(&mut ast);
     match ext.error {
         Some(err) => Err(err),
-        _ => Ok(ast),
+        _ => Ok((ast, ext.files)),
+    }
+}
+
+/// Files read by [`parse_file_recursive_with_files`], the root file first.
+#[derive(Debug, Default, Clone)]
+pub struct SourceFiles {
+    /// Each path with a span in it, `None` if the file is empty.
+    files: Vec<(PathBuf, Option<Span>)>,
+}
+
+impl SourceFiles {
+    fn push(&mut self, path: PathBuf, ast: &File) {
+        let span = (ast.attrs.first().map(|attr| attr.span()))
+            .or_else(|| ast.items.first().map(|item| item.span()));
+        self.files.push((path, span));
+    }
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(path, _)| path.as_path())
+    }
+    /// File containing `span`, spans of different files cannot be joined.
+    pub fn locate(&self, span: Span) -> Option<&Path> {
+        self.files
+            .iter()
+            .find(|(_, file)| file.is_some_and(|file| file.join(span).is_some()))
+            .map(|(path, _)| path.as_path())
     }
 }
 
@@ -49,9 +91,9 @@ pub fn parse_file_recursive(
 struct ExtractAst<'c> {
     mod_dir: PathBuf,
     cwd: PathBuf,
+    files: SourceFiles,
     error: Option<Error>,
-    cfg_enable: &'c [Meta],
-    cfg_disable: &'c [Meta],
+    cfg: &'c CfgSet,
 }
 
 impl ExtractAst<'_> {
@@ -82,6 +124,7 @@ impl ExtractAst<'_> {
     fn expand_file(&mut self, node: &mut ItemMod) -> Result<(), Error> {
         let path = self.find_mod_file(node)?;
         let ast = parse_file_from_path(&path)?;
+        self.files.push(path, &ast);
 
         node.attrs.extend(ast.attrs);
         let mut tokens = TokenStream::new();
@@ -133,10 +176,10 @@ impl VisitMut for ExtractAst<'_> {
     fn visit_item_mut(&mut self, node: &mut Item) {
         let mut is_skip = false;
         if let Some(attrs) = node.get_attributes_mut() {
-            if !check_cfg(attrs, self.cfg_enable, self.cfg_disable) {
+            if !check_cfg(attrs, self.cfg) {
                 is_skip = true;
             } else {
-                flatten_cfg_attr(attrs, self.cfg_enable, self.cfg_disable);
+                flatten_cfg_attr(attrs, self.cfg);
             }
         }
         if is_skip {
@@ -176,7 +219,7 @@ fn find_pathstr_from_attrs(attrs: &[Attribute]) -> Option<String> {
         })
 }
 
-fn check_cfg(attrs: &mut Vec<Attribute>, cfg_enable: &[Meta], cfg_disable: &[Meta]) -> bool {
+fn check_cfg(attrs: &mut Vec<Attribute>, cfg: &CfgSet) -> bool {
     let mut next = Vec::new();
     let mut cond = true;
     for attr in attrs.drain(..) {
@@ -184,7 +227,7 @@ fn check_cfg(attrs: &mut Vec<Attribute>, cfg_enable: &[Meta], cfg_disable: &[Met
             && let Meta::List(list) = &attr.meta
             && let Ok(pred) = list.parse_args()
         {
-            match cfg_condition(&pred, cfg_enable, cfg_disable) {
+            match cfg.evaluate(&pred) {
                 Some(true) => {}
                 Some(false) => cond = false,
                 None => next.push(attr),
@@ -197,7 +240,7 @@ fn check_cfg(attrs: &mut Vec<Attribute>, cfg_enable: &[Meta], cfg_disable: &[Met
     cond
 }
 
-fn flatten_cfg_attr(attrs: &mut Vec<Attribute>, cfg_enable: &[Meta], cfg_disable: &[Meta]) {
+fn flatten_cfg_attr(attrs: &mut Vec<Attribute>, cfg: &CfgSet) {
     let mut next = Vec::new();
     for attr in attrs.drain(..) {
         if attr.path().is_ident("cfg_attr")
@@ -206,7 +249,7 @@ fn flatten_cfg_attr(attrs: &mut Vec<Attribute>, cfg_enable: &[Meta], cfg_disable
         {
             let mut it = preds.iter();
             if let Some(pred) = it.next() {
-                match cfg_condition(pred, cfg_enable, cfg_disable) {
+                match cfg.evaluate(pred) {
                     Some(true) => {
                         next.extend(it.map(to_attribute));
                         continue;
@@ -237,82 +280,26 @@ fn test_to_attribute() {
     );
 }
 
-#[test]
-fn test_cfg_condition_enable_disable() {
-    let enable = vec![syn::parse_str::<Meta>("feature = \"foo\"").unwrap()];
-    let disable = vec![syn::parse_str::<Meta>("feature = \"bar\"").unwrap()];
-
-    let pred_enable = syn::parse_str::<Meta>("feature = \"foo\"").unwrap();
-    let pred_disable = syn::parse_str::<Meta>("feature = \"bar\"").unwrap();
-    let pred_unknown = syn::parse_str::<Meta>("target_arch = \"x86_64\"").unwrap();
-    let pred_any =
-        syn::parse_str::<Meta>("any(feature = \"foo\", target_arch = \"x86_64\")").unwrap();
-
-    assert_eq!(cfg_condition(&pred_enable, &enable, &disable), Some(true));
-    assert_eq!(cfg_condition(&pred_disable, &enable, &disable), Some(false));
-    assert_eq!(cfg_condition(&pred_unknown, &enable, &disable), None);
-    assert_eq!(cfg_condition(&pred_any, &enable, &disable), None);
-}
-
-fn cfg_condition(pred: &Meta, cfg_enable: &[Meta], cfg_disable: &[Meta]) -> Option<bool> {
-    if let Some(id) = pred.path().get_ident() {
-        match id.to_string().as_str() {
-            "all" => {
-                if let Meta::List(list) = pred {
-                    let preds = list
-                        .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
-                        .ok()?;
-                    let mut result = true;
-                    for pred in preds.iter() {
-                        match cfg_condition(pred, cfg_enable, cfg_disable) {
-                            Some(true) => {}
-                            Some(false) => result = false,
-                            None => return None,
-                        }
-                    }
-                    return Some(result);
-                }
-            }
-            "any" => {
-                if let Meta::List(list) = pred {
-                    let preds = list
-                        .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
-                        .ok()?;
-                    let mut result = false;
-                    for pred in preds.iter() {
-                        match cfg_condition(pred, cfg_enable, cfg_disable) {
-                            Some(true) => result = true,
-                            Some(false) => {}
-                            None => return None,
-                        }
-                    }
-                    return Some(result);
-                }
-            }
-            "not" => {
-                if let Meta::List(list) = pred {
-                    let pred = list.parse_args().ok()?;
-                    return cfg_condition(&pred, cfg_enable, cfg_disable).map(|pred| !pred);
-                }
-            }
-            _ => {}
-        }
-    }
-    if cfg_disable.iter().any(|spec| spec == pred) {
-        Some(false)
-    } else if cfg_enable.iter().any(|spec| spec == pred) {
-        Some(true)
-    } else {
-        None
-    }
-}
-
 #[test]
 fn test_parse() {
     let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "examples", "mod_path", "lib.rs"]
         .iter()
         .collect();
-    if let Err(err) = parse_file_recursive(path, &[], &[]) {
+    if let Err(err) = parse_file_recursive(path.clone(), &[], &[]) {
         panic!("{}", err);
     }
+    let (ast, files) = parse_file_recursive_with_files(path.clone(), &CfgSet::default()).unwrap();
+    let paths: Vec<_> = files.paths().collect();
+    assert_eq!(paths[0], path);
+    assert_eq!(paths.len(), 40);
+    assert!(paths.iter().all(|file| file.exists()));
+    let Some(Item::Mod(item)) = ast.items.first() else {
+        panic!("expected `mod a`");
+    };
+    assert_eq!(files.locate(item.ident.span()), Some(path.as_path()));
+    let doc = item.attrs.last().unwrap().span();
+    assert_eq!(
+        files.locate(doc),
+        Some(path.with_file_name("a.rs").as_path())
+    );
 }
@@ -0,0 +1,213 @@
+use proc_macro2::{Delimiter, Spacing, Span, TokenStream, TokenTree};
+
+/// Print tokens using the original source text where spans allow it.
+///
+/// Comments and whitespace between two tokens are kept when both tokens come from the same
+/// source and nothing was removed between them. Otherwise tokens are separated as
+/// `TokenStream::to_string` would do, keeping comments on the lines after the removed tokens.
+///
+/// Printing starts at `start`, so that comments after removed leading tokens are kept too.
+pub(crate) fn print_source(tokens: TokenStream, start: Option<Span>) -> String {
+    let mut printer = SourcePrinter {
+        start,
+        ..Default::default()
+    };
+    printer.print_stream(tokens);
+    printer.out
+}
+
+#[derive(Default)]
+struct SourcePrinter {
+    out: String,
+    start: Option<Span>,
+    prev: Option<Span>,
+    joint: bool,
+    line_comment: bool,
+}
+
+impl SourcePrinter {
+    fn print_stream(&mut self, tokens: TokenStream) {
+        for tt in tokens {
+            match tt {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    if !open.is_empty() {
+                        self.print_token(group.span_open(), open, false);
+                    }
+                    self.print_stream(group.stream());
+                    if !close.is_empty() {
+                        self.print_token(group.span_close(), close, false);
+                    }
+                }
+                TokenTree::Punct(punct) => self.print_token(
+                    punct.span(),
+                    &punct.to_string(),
+                    punct.spacing() == Spacing::Joint,
+                ),
+                tt => self.print_token(tt.span(), &tt.to_string(), false),
+            }
+        }
+    }
+
+    fn print_token(&mut self, span: Span, text: &str, joint: bool) {
+        let Some(source) = span.source_text() else {
+            if !self.joint {
+                self.push_separator(false);
+            }
+            self.out.push_str(text);
+            self.start = None;
+            self.prev = None;
+            self.joint = joint;
+            self.line_comment = false;
+            return;
+        };
+        if let Some(start) = self.start.take()
+            && self.prev.is_none()
+            && let Some(joined) = start.join(span)
+        {
+            let begin = joined.byte_range().start;
+            let range = span.byte_range();
+            if begin == start.byte_range().start && range.start >= begin {
+                let text = joined.source_text().unwrap_or_default();
+                self.out
+                    .push_str(trailing_comments(&text[..range.start - begin]));
+            }
+        }
+        if let Some(prev) = self.prev
+            && let Some(joined) = prev.join(span)
+        {
+            let prev_end = prev.byte_range().end;
+            let range = span.byte_range();
+            if range.end <= prev_end {
+                // already printed, e.g. the tokens of a doc comment
+                return;
+            }
+            if range.start >= prev_end {
+                let start = joined.byte_range().start;
+                let text = joined.source_text().unwrap_or_default();
+                let gap = &text[prev_end - start..range.start - start];
+                if is_trivia(gap) {
+                    self.out.push_str(gap);
+                } else {
+                    self.push_separator(gap.contains('\n'));
+                    self.out.push_str(trailing_comments(gap));
+                }
+                self.push_source(span, &source);
+                return;
+            }
+        }
+        self.push_separator(false);
+        self.push_source(span, &source);
+    }
+
+    fn push_source(&mut self, span: Span, source: &str) {
+        self.out.push_str(source);
+        self.prev = Some(span);
+        self.joint = false;
+        self.line_comment = source.starts_with("//");
+    }
+
+    fn push_separator(&mut self, newline: bool) {
+        if self
+            .out
+            .chars()
+            .next_back()
+            .is_none_or(|ch| ch.is_whitespace())
+        {
+            return;
+        }
+        self.out.push(if newline || self.line_comment {
+            '\n'
+        } else {
+            ' '
+        });
+    }
+}
+
+/// Comments on the lines after the last token in `gap`.
+fn trailing_comments(gap: &str) -> &str {
+    gap.match_indices('\n')
+        .map(|(index, _)| &gap[index + 1..])
+        .find(|rest| is_trivia(rest))
+        .filter(|rest| !rest.trim().is_empty())
+        .unwrap_or_default()
+}
+
+fn is_trivia(gap: &str) -> bool {
+    gap.trim().is_empty()
+        || gap
+            .parse::<TokenStream>()
+            .is_ok_and(|tokens| tokens.is_empty())
+}
+
+#[test]
+fn test_print_source() {
+    use quote::ToTokens as _;
+    use syn::{Item, ItemFn, ItemMod, parse_quote, parse_str, spanned::Spanned as _};
+
+    let mut item: ItemFn = parse_str(
+        r#"/// doc
+#[codesnip::entry]
+pub fn gcd(mut a: u64, mut b: u64) -> u64 {
+    // keep this comment
+    while b != 0 {
+        a %= b; /* and this */
+        std::mem::swap(&mut a, &mut b);
+    }
+    a
+}"#,
+    )
+    .unwrap();
+    item.attrs.remove(1);
+    item.attrs.push(parse_quote!(#[inline]));
+    assert_eq!(
+        print_source(Item::Fn(item).into_token_stream(), None),
+        r#"/// doc
+# [ inline ] pub fn gcd(mut a: u64, mut b: u64) -> u64 {
+    // keep this comment
+    while b != 0 {
+        a %= b; /* and this */
+        std::mem::swap(&mut a, &mut b);
+    }
+    a
+}"#
+    );
+    // Comments after a removed attribute at the start or in the middle.
+    for (source, expected) in [
+        (
+            "#[codesnip::entry]\n// why this works\npub fn a() {}",
+            "// why this works\npub fn a() {}",
+        ),
+        (
+            "/// doc\n#[codesnip::entry]\n// why this works\npub fn a() {}",
+            "/// doc\n// why this works\npub fn a() {}",
+        ),
+        (
+            "#[codesnip::entry] // same line\npub fn a() {}",
+            "pub fn a() {}",
+        ),
+    ] {
+        let mut item: ItemFn = parse_str(source).unwrap();
+        let start = Some(item.span());
+        item.attrs.retain(|attr| attr.path().is_ident("doc"));
+        assert_eq!(
+            print_source(Item::Fn(item).into_token_stream(), start),
+            expected
+        );
+    }
+
+    // Leading comments of an inline module.
+    let item: ItemMod =
+        parse_str("mod m {\n    // inner\n    fn a() {}\n\n    fn b() {}\n}").unwrap();
+    let (brace, items) = item.content.unwrap();
+    let tokens = items.iter().map(|item| item.into_token_stream()).collect();
+    assert_eq!(
+        print_source(tokens, Some(brace.span.open())),
+        "    // inner\n    fn a() {}\n\n    fn b() {}"
+    );
+}